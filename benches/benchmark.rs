@@ -1,11 +1,12 @@
-use bfield::BField;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::thread;
+
+use bfield::{BField, PrefetchLocality};
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput,
+};
 
 fn build_bfield(n_secondaries: u8) -> BField<String> {
-    let tmp_dir = tempfile::tempdir().unwrap();
-    BField::create(
-        tmp_dir.path(),
-        "bfield",
+    BField::in_memory(
         1_000_000,
         10,
         39,
@@ -13,7 +14,6 @@ fn build_bfield(n_secondaries: u8) -> BField<String> {
         0.1,
         0.025,
         n_secondaries,
-        false,
         String::new(),
     )
     .expect("to build")
@@ -22,7 +22,26 @@ fn build_bfield(n_secondaries: u8) -> BField<String> {
 fn bench_insertion(c: &mut Criterion) {
     let mut bfield = build_bfield(4);
     c.bench_function("bfield insertion", |b| {
-        b.iter(|| bfield.insert(&1_u32.to_be_bytes().to_vec(), 1_u32, 0))
+        b.iter(|| bfield.insert(1_u64.to_be_bytes(), 1_u64, 0))
+    });
+}
+
+fn bench_bulk_insertion(c: &mut Criterion) {
+    let keys: Vec<[u8; 8]> = (0..10_000_u64).map(|i| i.to_be_bytes()).collect();
+    let items: Vec<(&[u8], u64)> = keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+
+    let bfield = build_bfield(4);
+    c.bench_function("bfield insert loop", |b| {
+        b.iter(|| {
+            for (key, value) in &items {
+                bfield.insert(key, *value, 0);
+            }
+        })
+    });
+
+    let bfield = build_bfield(4);
+    c.bench_function("bfield insert_many", |b| {
+        b.iter(|| bfield.insert_many(black_box(&items), 0).unwrap())
     });
 }
 
@@ -30,17 +49,272 @@ fn bench_querying(c: &mut Criterion) {
     let mut bfield = build_bfield(4);
 
     // Identity database
-    let max_value: u32 = 10_000;
+    let max_value: u64 = 10_000;
     for p in 0..4 {
         for i in 0..max_value {
-            bfield.insert(&i.to_be_bytes().to_vec(), i, p as usize);
+            bfield.insert(i.to_be_bytes(), i, p as usize);
         }
     }
 
     c.bench_function("bfield querying", |b| {
-        b.iter(|| black_box(bfield.get(black_box(&10_000_i32.to_be_bytes().to_vec()))))
+        b.iter(|| black_box(bfield.get(black_box(&10_000_u64.to_be_bytes().to_vec()))))
+    });
+}
+
+fn bench_single_member_querying(c: &mut Criterion) {
+    let bfield = build_bfield(1);
+    for i in 0..10_000_u64 {
+        bfield.insert(i.to_be_bytes(), i, 0);
+    }
+    let keys: Vec<[u8; 8]> = (0..20_000_u64).map(|i| i.to_be_bytes()).collect();
+
+    c.bench_function("bfield single member get loop", |b| {
+        b.iter(|| {
+            keys.iter()
+                .map(|key| bfield.get(black_box(key)))
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+fn bench_bulk_querying(c: &mut Criterion) {
+    let bfield = build_bfield(4);
+    let keys: Vec<[u8; 8]> = (0..10_000_u64).map(|i| i.to_be_bytes()).collect();
+    let items: Vec<(&[u8], u64)> = keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+    for p in 0..4 {
+        bfield.insert_many(&items, p).unwrap();
+    }
+    // query as many missing keys as present ones
+    let keys: Vec<[u8; 8]> = (0..20_000_u64).map(|i| i.to_be_bytes()).collect();
+    let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+
+    c.bench_function("bfield get loop", |b| {
+        b.iter(|| {
+            keys.iter()
+                .map(|key| bfield.get(black_box(key)))
+                .collect::<Vec<_>>()
+        })
+    });
+    c.bench_function("bfield get_many", |b| {
+        b.iter(|| bfield.get_many(black_box(&keys)))
     });
 }
 
-criterion_group!(benches, bench_insertion, bench_querying);
+fn bench_in_memory_querying(c: &mut Criterion) {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let bfield = BField::create(
+        tmp_dir.path(),
+        "bfield",
+        10_000_000,
+        10,
+        39,
+        4,
+        0.1,
+        0.025,
+        4,
+        false,
+        String::new(),
+    )
+    .expect("to build");
+    let keys: Vec<[u8; 8]> = (0..100_000_u64).map(|i| i.to_be_bytes()).collect();
+    // 39 choose 4 caps the values at 82_250
+    let items: Vec<(&[u8], u64)> = keys
+        .iter()
+        .zip((0..10_000).cycle())
+        .map(|(k, v)| (&k[..], v))
+        .collect();
+    for p in 0..4 {
+        bfield.insert_many(&items, p).unwrap();
+    }
+    drop(bfield);
+
+    // spread the lookups over the whole arrays rather than hitting the same pages
+    let path = tmp_dir.path().join("bfield.0.bfd");
+    let keys: Vec<[u8; 8]> = (0..10_000_u64)
+        .map(|i| (i * 7_919 % 200_000).to_be_bytes())
+        .collect();
+    let mapped = BField::<String>::load(&path, true).unwrap();
+    c.bench_function("bfield get mmap", |b| {
+        b.iter(|| {
+            keys.iter()
+                .map(|key| mapped.get(black_box(key)))
+                .collect::<Vec<_>>()
+        })
+    });
+    let in_memory = BField::<String>::load_into_memory(&path).unwrap();
+    c.bench_function("bfield get in memory", |b| {
+        b.iter(|| {
+            keys.iter()
+                .map(|key| in_memory.get(black_box(key)))
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+fn bench_first_query(c: &mut Criterion) {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let bfield = BField::create(
+        tmp_dir.path(),
+        "bfield",
+        10_000_000,
+        10,
+        39,
+        4,
+        0.1,
+        0.025,
+        4,
+        false,
+        String::new(),
+    )
+    .expect("to build");
+    let keys: Vec<[u8; 8]> = (0..100_000_u64).map(|i| i.to_be_bytes()).collect();
+    // 39 choose 4 caps the values at 82_250
+    let items: Vec<(&[u8], u64)> = keys
+        .iter()
+        .zip((0..10_000).cycle())
+        .map(|(k, v)| (&k[..], v))
+        .collect();
+    for p in 0..4 {
+        bfield.insert_many(&items, p).unwrap();
+    }
+    drop(bfield);
+
+    // the files stay in the page cache, so "cold" only measures faulting in a fresh
+    // mapping rather than reading from disk
+    let path = tmp_dir.path().join("bfield.0.bfd");
+    let keys: Vec<[u8; 8]> = (0..100_u64)
+        .map(|i| (i * 7_919 % 200_000).to_be_bytes())
+        .collect();
+    for (name, preload) in [("cold", false), ("warm", true)] {
+        c.bench_function(&format!("bfield first queries {name}"), |b| {
+            b.iter_batched(
+                || {
+                    let bfield = BField::<String>::load(&path, true).unwrap();
+                    if preload {
+                        bfield.preload().unwrap();
+                    }
+                    bfield
+                },
+                |bfield| {
+                    keys.iter()
+                        .map(|key| bfield.get(black_box(key)))
+                        .collect::<Vec<_>>()
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+}
+
+fn bench_prefetch_locality(c: &mut Criterion) {
+    let mut bfield = build_bfield(4);
+    let keys: Vec<[u8; 8]> = (0..10_000_u64).map(|i| i.to_be_bytes()).collect();
+    let items: Vec<(&[u8], u64)> = keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+    for p in 0..4 {
+        bfield.insert_many(&items, p).unwrap();
+    }
+    let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+
+    for (name, locality) in [
+        ("non-temporal", PrefetchLocality::NonTemporal),
+        ("high", PrefetchLocality::High),
+    ] {
+        bfield.set_prefetch_locality(locality);
+        // a scan over many keys, which never reads the same markers twice
+        c.bench_function(&format!("bfield get_many scan {name}"), |b| {
+            b.iter(|| bfield.get_many(black_box(&keys)))
+        });
+        // point lookups hitting the same few markers over and over
+        c.bench_function(&format!("bfield get_many hot {name}"), |b| {
+            b.iter(|| bfield.get_many(black_box(&keys[..16])))
+        });
+    }
+}
+
+fn bench_miss_heavy_querying(c: &mut Criterion) {
+    // too big for the CPU caches, where prefetching markers that are never read costs the most
+    let mut bfield =
+        BField::in_memory(200_000_000, 10, 39, 4, 0.1, 0.025, 4, String::new()).expect("to build");
+    let keys: Vec<[u8; 8]> = (0..10_000_u64).map(|i| i.to_be_bytes()).collect();
+    let items: Vec<(&[u8], u64)> = keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+    for p in 0..4 {
+        bfield.insert_many(&items, p).unwrap();
+    }
+    // keys that were never inserted, so most lookups end on their first few markers
+    let misses: Vec<[u8; 8]> = (10_000..20_000_u64).map(|i| i.to_be_bytes()).collect();
+
+    for probe_markers in [0, 1, 2] {
+        bfield.set_probe_markers(probe_markers);
+        c.bench_function(&format!("bfield get misses probe {probe_markers}"), |b| {
+            b.iter(|| {
+                misses
+                    .iter()
+                    .map(|key| bfield.get(black_box(key)))
+                    .collect::<Vec<_>>()
+            })
+        });
+    }
+}
+
+fn bench_concurrent_querying(c: &mut Criterion) {
+    // bigger than the CPU caches, so threads compete for memory bandwidth like they
+    // would on a real index
+    let bfield =
+        BField::in_memory(100_000_000, 10, 39, 4, 0.1, 0.025, 4, String::new()).expect("to build");
+    let keys: Vec<[u8; 8]> = (0..1_000_000_u64).map(|i| i.to_be_bytes()).collect();
+    let items: Vec<(&[u8], u64)> = keys
+        .iter()
+        .zip((0..80_000).cycle())
+        .map(|(k, v)| (&k[..], v))
+        .collect();
+    for p in 0..4 {
+        bfield.insert_many(&items, p).unwrap();
+    }
+
+    let per_thread = 20_000_u64;
+    let max_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut group = c.benchmark_group("bfield concurrent get");
+    let mut n_threads = 1;
+    while n_threads <= max_threads {
+        group.throughput(Throughput::Elements(n_threads as u64 * per_thread));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(n_threads),
+            &n_threads,
+            |b, &n_threads| {
+                b.iter(|| {
+                    thread::scope(|scope| {
+                        for t in 0..n_threads as u64 {
+                            let bfield = &bfield;
+                            scope.spawn(move || {
+                                // scattered keys, half of which were never inserted
+                                for i in 0..per_thread {
+                                    let key = (i + t * per_thread)
+                                        .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+                                        % 2_000_000;
+                                    black_box(bfield.get(key.to_be_bytes()));
+                                }
+                            });
+                        }
+                    })
+                })
+            },
+        );
+        n_threads *= 2;
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_insertion,
+    bench_bulk_insertion,
+    bench_querying,
+    bench_single_member_querying,
+    bench_bulk_querying,
+    bench_in_memory_querying,
+    bench_first_query,
+    bench_prefetch_locality,
+    bench_miss_heavy_querying,
+    bench_concurrent_querying
+);
 criterion_main!(benches);
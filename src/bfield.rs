@@ -1,21 +1,147 @@
-use std::io;
+use std::convert::TryFrom;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
 
-use crate::combinatorial::rank;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 
-use crate::bfield_member::{BFieldLookup, BFieldMember, BFieldVal};
+use crate::bfield_member::{
+    AccessPattern, BFieldConfig, BFieldLookup, BFieldMember, BFieldParams, BFieldVal,
+    ExpectedParams, MaskOutcome, MemberInfo, NonZeroBFieldVal, PositionScheme, PrefetchLocality,
+    SecondaryParams,
+};
+use crate::builder::BFieldBuilder;
+use crate::error::{BFieldError, ChecksumError, Indeterminate, InsertError, MergeError};
+use crate::hasher::{BFieldHasher, Murmur3};
+use crate::read_only::{ReadOnlyBField, Writable};
+use crate::sizing::{member_sizes, plan_layout, Layout};
+
+/// Magic bytes at the start of the single-file format of `BField::save_single`
+const SINGLE_MAGIC: [u8; 4] = *b"BFLD";
+/// Version of the single-file layout
+const SINGLE_VERSION: u16 = 1;
+/// Size of the fixed part of a single file: magic, version and number of members
+const SINGLE_PREAMBLE_LEN: u64 = 8;
+/// Size of each member entry of the table of contents: header length, size and offset
+const SINGLE_TOC_ENTRY_LEN: u64 = 20;
+/// Number of keys each rayon task of `par_get_many` looks up with `get_many`
+#[cfg(feature = "rayon")]
+const PAR_GET_CHUNK: usize = 4096;
+
+/// What `BField::force_insert_checked` did with a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForceInsertOutcome {
+    /// The key wasn't present and got inserted without masking anything.
+    Inserted,
+    /// The key was already present with the same value.
+    AlreadyPresent,
+    /// The key resolved to a different value, which got masked to indeterminate.
+    /// `inserted` tells whether a later member then took the new value; if not,
+    /// the key is now indeterminate in every member.
+    Masked {
+        /// Whether the new value ended up inserted in a later member
+        inserted: bool,
+    },
+    /// The key was already indeterminate in every member, so nothing was inserted.
+    Indeterminate,
+}
+
+/// What `BField::force_insert_many` and `BField::mask_many` did with a batch of keys,
+/// to judge whether the `BField` is still usable after a bulk correction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MaskStats {
+    /// Keys whose value got inserted, possibly after masking a previous value
+    pub inserted: u64,
+    /// Keys that were already present with the same value
+    pub already_present: u64,
+    /// Keys whose previous value got masked
+    pub masked: u64,
+    /// Keys that weren't present, so there was nothing to mask
+    pub absent: u64,
+    /// Keys left indeterminate in every member
+    pub indeterminate: u64,
+    /// Bits that went from 0 to 1 across all the members. Each bit set by masking can
+    /// also turn other keys whose markers overlap it indeterminate.
+    pub bits_set: u64,
+}
+
+/// How a build simulated by `BField::simulate_build` over a sample of keys turned out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildReport {
+    /// The size of each array the sample was built into, scaled down from the config
+    pub sizes: Vec<usize>,
+    /// How many sampled keys were inserted in each array, i.e. were indeterminate in all
+    /// the ones before it
+    pub inserted: Vec<usize>,
+    /// The fraction of bits set in each array once built, see `BField::saturation`
+    pub fill_ratios: Vec<f64>,
+    /// The fraction of the sampled keys indeterminate in every array, for which `get`
+    /// would return `None`
+    pub indeterminate_rate: f64,
+}
+
+/// What `BField::get`, `BField::try_get`, `BField::get_many` and `BField::contains_key`
+/// do with keys that are indeterminate in every member, which usually means the
+/// `BField` is undersized or saturated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IndeterminateBehavior {
+    /// Return `None`, like for absent keys
+    #[default]
+    ReturnNone,
+    /// Panic, for when a false negative is worse than crashing
+    Panic,
+    /// Return `Err(Indeterminate)` from `try_get`, for callers that want to handle it
+    /// without crashing. The infallible lookups return `None` as for `ReturnNone`.
+    ReturnErr,
+}
 
 /// The `struct` holding the `BField` primary and secondary bit arrays.
-pub struct BField<T> {
-    members: Vec<BFieldMember<T>>,
+///
+/// A `BField` is `Send` and `Sync` whenever `T` and the hasher `H` are, so an `Arc<BField<T>>` can be queried
+/// from several threads at once without locking. The insertion methods also only take
+/// `&self` though, and they must not run at the same time as other inserts or lookups
+/// on the same `BField`: build it from a single thread (or with `par_build`) first,
+/// or load it as `read_only`, which rejects inserts altogether.
+///
+/// The one exception is `try_insert_atomic`, which several threads can call at once
+/// as long as they all insert into the same pass and nothing else (inserts, lookups,
+/// `clear`, `merge`...) runs in the meantime.
+///
+/// `M` is `Writable` for the `BField`s returned by `create` and `load`, and `ReadOnly`
+/// for a `ReadOnlyBField`, which only has the query methods.
+pub struct BField<T, H = Murmur3, M = Writable> {
+    members: Vec<BFieldMember<T, H>>,
     read_only: bool,
+    indeterminate_behavior: IndeterminateBehavior,
+    // `fn() -> M` rather than `M` so the mode doesn't affect `Send` and `Sync`
+    mode: PhantomData<fn() -> M>,
+}
+
+impl<T: fmt::Debug, H: BFieldHasher, M> fmt::Debug for BField<T, H, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BField")
+            .field("n_members", &self.members.len())
+            .field("read_only", &self.read_only)
+            .field("indeterminate_behavior", &self.indeterminate_behavior)
+            .field("other", &self.members[0].params.other)
+            .field("members", &self.members)
+            .finish()
+    }
 }
 
-// This is safe in theory, as the mmap is send+sync
-unsafe impl<T> Send for BField<T> {}
-unsafe impl<T> Sync for BField<T> {}
+impl<T, H, M> Drop for BField<T, H, M> {
+    fn drop(&mut self) {
+        // save the insert count of a `BField` built on disk so `len` still has it once
+        // loaded again; there's no way to report a failure from here
+        if let Some(primary) = self.members.first() {
+            let _ = primary.write_n_inserted();
+        }
+    }
+}
 
 impl<T: Clone + DeserializeOwned + Serialize> BField<T> {
     /// A (rather complex) method for creating a `BField`.
@@ -38,6 +164,9 @@ impl<T: Clone + DeserializeOwned + Serialize> BField<T> {
     /// `n_secondaries` can be impractically/needlessly small.
     /// - `n_secondaries`. The number of secondary `BField`s to create.
     /// - `in_memory`. Whether to create the `BField` in memory or on disk.
+    ///
+    /// Fails with an `InvalidInput` error if `filename` is empty, `.`, `..` or contains a
+    /// path separator.
     #[allow(clippy::too_many_arguments)]
     pub fn create<P>(
         directory: P,
@@ -55,48 +184,273 @@ impl<T: Clone + DeserializeOwned + Serialize> BField<T> {
     where
         P: AsRef<Path>,
     {
-        debug_assert!(!filename.is_empty());
-        let mut cur_size = size;
-        let mut members = Vec::new();
+        Self::create_with_seed(
+            directory,
+            filename,
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            n_secondaries,
+            in_memory,
+            0,
+            other_params,
+        )
+    }
 
-        for n in 0..n_secondaries {
-            let file = directory.as_ref().join(format!("{filename}.{n}.bfd"));
-            let params = if n == 0 {
-                Some(other_params.clone())
-            } else {
-                None
-            };
-            let member = BFieldMember::create(
-                file,
-                in_memory,
-                cur_size,
-                n_hashes,
-                marker_width,
-                n_marker_bits,
-                params,
-            )?;
-            members.push(member);
-            cur_size = f64::max(
-                cur_size as f64 * secondary_scaledown,
-                size as f64 * max_scaledown,
-            ) as usize;
+    /// Same as `create` but picks the size and number of arrays for `n_keys` keys with
+    /// `plan_layout`, so that at most about `target_rate` of them end up indeterminate
+    /// in every array. The `Layout` that was built is returned as well, e.g. to log it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_auto<P>(
+        directory: P,
+        filename: &str,
+        n_keys: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        in_memory: bool,
+        target_rate: f64,
+        other_params: T,
+    ) -> Result<(Self, Layout), io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        if !(target_rate > 0. && target_rate < 1.) {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "target_rate is {} but needs to be in (0, 1)",
+                target_rate
+            ))));
+        }
+        validate_scaledowns(secondary_scaledown, max_scaledown)?;
+        if n_hashes == 0 {
+            return Err(io::Error::from(BFieldError::InvalidParams(
+                "n_hashes needs to be at least 1".to_string(),
+            )));
+        }
+        let layout = plan_layout(
+            n_keys,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            target_rate,
+        )
+        .ok_or_else(|| {
+            io::Error::from(BFieldError::InvalidParams(format!(
+                "a target_rate of {} would need more than 255 arrays",
+                target_rate
+            )))
+        })?;
+        let bfield = Self::create(
+            directory,
+            filename,
+            layout.sizes[0],
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            layout.n_secondaries(),
+            in_memory,
+            other_params,
+        )?;
+        Ok((bfield, layout))
+    }
+
+    /// Same as `create` but hashes keys with the given murmur `seed` instead of 0.
+    ///
+    /// `BField`s built over the same keys with different seeds have independent
+    /// hash layouts, e.g. for sharding or measuring false positive correlation.
+    /// The seed is saved in the file headers and used again by `load`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_seed<P>(
+        directory: P,
+        filename: &str,
+        size: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        n_secondaries: u8,
+        in_memory: bool,
+        seed: u64,
+        other_params: T,
+    ) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+    {
+        // the array files need to end up right in `directory` for `load` to find them
+        if Path::new(filename).file_name() != Some(OsStr::new(filename)) {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "filename {:?} needs to be a file name without any directory",
+                filename
+            ))));
         }
+        Self::create_with_naming(
+            |n| directory.as_ref().join(format!("{filename}.{n}.bfd")),
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            n_secondaries,
+            in_memory,
+            seed,
+            other_params,
+        )
+    }
 
-        // Initialize our marker table, so we don't
-        // have any race conditions across threads
-        let _ = rank(0, n_marker_bits);
+    /// Same as `create_with_seed` but the path of each array file is given by `naming`,
+    /// called with the array index (0 for the primary array), for when the files need
+    /// to follow some external convention rather than `{filename}.{n}.bfd`.
+    /// Such `BField`s can be loaded again with `load_with_naming`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_naming<F>(
+        naming: F,
+        size: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        n_secondaries: u8,
+        in_memory: bool,
+        seed: u64,
+        other_params: T,
+    ) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        Self::create_with_hasher(
+            naming,
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            n_secondaries,
+            in_memory,
+            seed,
+            Murmur3,
+            other_params,
+        )
+    }
 
-        Ok(BField {
-            members,
-            read_only: false,
+    /// Same as `create_with_naming` but with an explicit path for each array, e.g. to put
+    /// them on different disks so they can be read in parallel. There must be exactly
+    /// `n_secondaries` paths, starting with the primary array's. Such `BField`s can be
+    /// loaded again with `load_with_paths`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_paths(
+        paths: &[PathBuf],
+        size: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        n_secondaries: u8,
+        in_memory: bool,
+        seed: u64,
+        other_params: T,
+    ) -> Result<Self, io::Error> {
+        if paths.len() != usize::from(n_secondaries) {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "got {} paths for a B-field with {} arrays",
+                paths.len(),
+                n_secondaries
+            ))));
+        }
+        Self::create_with_naming(
+            |n| paths[n].clone(),
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            n_secondaries,
+            in_memory,
+            seed,
+            other_params,
+        )
+    }
+
+    /// Creates an empty `BField` on disk laid out as described by `config`, e.g. one
+    /// returned by `config` on another `BField`. `path` names the array files like in
+    /// `BFieldBuilder::build`.
+    ///
+    /// Fails with an `InvalidInput` error if `config` wasn't for murmur3-hashed keys,
+    /// see `create_from_config_with_hasher`.
+    pub fn create_from_config<P: AsRef<Path>>(
+        path: P,
+        config: &BFieldConfig,
+        other_params: T,
+    ) -> Result<Self, io::Error> {
+        Self::create_from_config_with_hasher(path, config, Murmur3, other_params)
+    }
+
+    /// Estimates how a build laid out as `config` over `n_keys` keys would turn out by
+    /// building it in memory over a sample of them, with every array scaled down by the
+    /// same ratio as the keys, e.g. to tune the parameters before a build that takes
+    /// hours. Every pass of the build is done, like with `insert_many`, and nothing is
+    /// written to disk.
+    ///
+    /// The sample needs to be representative (e.g. drawn at random) and large enough
+    /// for the smallest scaled array to stay wider than `marker_width`, otherwise an
+    /// `InvalidInput` error is returned.
+    pub fn simulate_build(
+        sample: &[(&[u8], BFieldVal)],
+        n_keys: usize,
+        config: &BFieldConfig,
+    ) -> Result<BuildReport, io::Error> {
+        if sample.is_empty() || sample.len() > n_keys {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "the sample has {} keys but needs between 1 and n_keys ({})",
+                sample.len(),
+                n_keys
+            ))));
+        }
+        let ratio = sample.len() as f64 / n_keys as f64;
+        let mut scaled = config.clone();
+        scaled.sizes = config
+            .sizes
+            .iter()
+            .map(|size| (*size as f64 * ratio).ceil() as usize)
+            .collect();
+        let bfield = Self::from_config(|_| PathBuf::new(), true, &scaled, Murmur3, None)?;
+
+        let mut inserted = Vec::with_capacity(bfield.members.len());
+        for pass in 0..bfield.members.len() {
+            inserted.push(
+                bfield
+                    .insert_many(sample, pass)
+                    .map_err(BFieldError::from)?,
+            );
+        }
+        let n_indeterminate = sample
+            .iter()
+            .filter(|(key, _)| bfield.lookup(key) == BFieldLookup::Indeterminate)
+            .count();
+        Ok(BuildReport {
+            sizes: scaled.sizes,
+            inserted,
+            fill_ratios: bfield.saturation(),
+            indeterminate_rate: n_indeterminate as f64 / sample.len() as f64,
         })
     }
 
     /// Loads the `BField` given the path to the primary array data file (eg the one ending with `0.bfd`).
     pub fn load<P: AsRef<Path>>(main_db_path: P, read_only: bool) -> Result<Self, io::Error> {
-        let mut members = Vec::new();
-        let mut n = 0;
-
         let main_db_filename = match main_db_path.as_ref().file_name() {
             Some(p) => p.to_string_lossy(),
             None => {
@@ -106,209 +460,4165 @@ impl<T: Clone + DeserializeOwned + Serialize> BField<T> {
                 ));
             }
         };
-        assert!(main_db_path.as_ref().parent().is_some());
-        assert!(main_db_filename.ends_with("0.bfd"));
-
-        loop {
-            let member_filename =
-                PathBuf::from(&main_db_filename.replace("0.bfd", &format!("{n}.bfd")));
-            let member_path = main_db_path
-                .as_ref()
-                .parent()
-                .unwrap()
-                .join(member_filename);
-            if !member_path.exists() {
-                break;
+        let directory = match main_db_path.as_ref().parent() {
+            Some(directory) if main_db_filename.ends_with("0.bfd") => directory,
+            _ => {
+                return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "{:?} isn't the path of a primary array ending with 0.bfd",
+                    main_db_path.as_ref()
+                ))));
             }
-            let member = BFieldMember::open(&member_path, read_only)?;
-            members.push(member);
-            n += 1;
-        }
-
-        if members.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::NotFound,
-                format!("No Bfield found at {:?}", main_db_path.as_ref()),
-            ));
-        }
-        Ok(BField { members, read_only })
+        };
+        Self::load_with_naming(
+            |n| directory.join(main_db_filename.replace("0.bfd", &format!("{n}.bfd"))),
+            read_only,
+        )
     }
 
-    /// Write the current `BField` to disk.
-    /// Only useful if you are creating a `BField` in memory.
-    pub fn persist_to_disk(self) -> Result<Self, io::Error> {
-        let mut members = Vec::with_capacity(self.members.len());
-        for m in self.members {
-            members.push(m.persist_to_disk()?);
-        }
-        Ok(Self {
-            members,
-            read_only: self.read_only,
-        })
+    /// Same as `load` but also checks every array against the checksum saved by
+    /// `persist_to_disk`, which means reading all of them once.
+    pub fn load_verified<P: AsRef<Path>>(
+        main_db_path: P,
+        read_only: bool,
+    ) -> Result<Self, io::Error> {
+        let bfield = Self::load(main_db_path, read_only)?;
+        bfield.verify().map_err(BFieldError::from)?;
+        Ok(bfield)
     }
 
-    /// Returns `(n_hashes, marker_width, n_marker_bits, Vec<size of each member>)`.
-    pub fn build_params(&self) -> (u8, u8, u8, Vec<usize>) {
-        let (_, n_hashes, marker_width, n_marker_bits) = self.members[0].info();
-        let sizes = self.members.iter().map(|i| i.info().0).collect();
-        (n_hashes, marker_width, n_marker_bits, sizes)
+    /// Same as `load` but fails with an `InvalidData` error unless every array was built
+    /// with the `expected` parameters, to catch loading the wrong file in pipelines that
+    /// juggle many `BField`s.
+    pub fn load_expecting<P: AsRef<Path>>(
+        main_db_path: P,
+        read_only: bool,
+        expected: ExpectedParams,
+    ) -> Result<Self, io::Error> {
+        let bfield = Self::load(main_db_path, read_only)?;
+        for member in &bfield.members {
+            member.check_expected(&expected)?;
+        }
+        Ok(bfield)
     }
 
-    /// Returns the params given at build time to the `BField` arrays.
-    pub fn params(&self) -> &Option<T> {
-        &self.members[0].params.other
+    /// Same as `load` but copies the arrays into memory and drops the file mappings, so
+    /// lookups never page fault at the cost of reading all the files up front. The
+    /// result can be inserted into without changing the files, unless it's persisted.
+    pub fn load_into_memory<P: AsRef<Path>>(main_db_path: P) -> Result<Self, io::Error> {
+        let bfield = Self::load(main_db_path, true)?;
+        // the files are read once from start to end
+        bfield.advise(AccessPattern::Sequential)?;
+        bfield.try_clone()
     }
 
-    /// ⚠️ Method for setting parameters without actually updating any files on disk. **Only useful for supporting legacy file formats
-    /// in which these parameters are not saved.**
-    pub fn mock_params(&mut self, params: T) {
-        self.members[0].params.other = Some(params);
+    /// Loads a `BField` whose array files are named by `naming` (see `create_with_naming`),
+    /// trying indices from 0 up until a file doesn't exist.
+    pub fn load_with_naming<F>(naming: F, read_only: bool) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        Self::load_with_hasher(naming, read_only, Murmur3)
     }
 
-    /// ⚠️ Method for inserting a value into a `BField`
-    /// after it has been fully built and finalized.
-    /// **This method should be used with extreme care**
-    /// as it does not guarantee that keys are properly propagated
-    /// to secondary arrays and therefore may make lookups of previously
-    /// set values return an indeterminate result in the primary array,
-    /// then causing fallback to the secondary arrays where they were never
-    /// inserted (and returning a false negative).
-    pub fn force_insert(&self, key: &[u8], value: BFieldVal) {
-        debug_assert!(!self.read_only, "Can't insert into read_only bfields");
-        for secondary in &self.members {
-            if secondary.mask_or_insert(key, value) {
-                break;
-            }
+    /// Loads a `BField` created with `create_with_paths` from the same `paths`, in the
+    /// same order. Fails with an `InvalidInput` error if the `BField` has a different
+    /// number of arrays and an `InvalidData` one if the first path isn't a primary array
+    /// or if an array doesn't have the size expected at its position, e.g. because two
+    /// paths were swapped.
+    pub fn load_with_paths(paths: &[PathBuf], read_only: bool) -> Result<Self, io::Error> {
+        let primary_path = paths.first().ok_or_else(|| {
+            io::Error::from(BFieldError::InvalidParams(
+                "no paths to load a B-field from".to_string(),
+            ))
+        })?;
+        let primary = BFieldMember::open(primary_path, read_only, Murmur3)?;
+        let secondaries = primary.params.secondaries.ok_or_else(|| {
+            io::Error::from(BFieldError::Corrupt(format!(
+                "{:?} isn't the primary array of a B-field",
+                primary_path
+            )))
+        })?;
+        if usize::from(secondaries.n_secondaries) != paths.len() {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "got {} paths for a B-field with {} arrays",
+                paths.len(),
+                secondaries.n_secondaries
+            ))));
         }
-    }
-
-    /// Insert the given key/value at the given pass (1-indexed `BField` array/member).
-    /// Returns whether the value was inserted during this call, i.e., will return `false` if
-    /// the value was already present.
-    pub fn insert(&self, key: &[u8], value: BFieldVal, pass: usize) -> bool {
-        debug_assert!(!self.read_only, "Can't insert into read_only bfields");
-        debug_assert!(
-            pass < self.members.len(),
-            "Can't have more passes than bfield members"
+        let sizes = member_sizes(
+            primary.member_info().size,
+            secondaries.secondary_scaledown,
+            secondaries.max_scaledown,
+            secondaries.n_secondaries,
         );
-        if pass > 0 {
-            for secondary in self.members[..pass].iter() {
-                match secondary.get(key) {
-                    BFieldLookup::Indeterminate => continue,
-                    _ => return false,
-                }
+        let mut members = vec![primary];
+        for (path, expected) in paths.iter().zip(sizes).skip(1) {
+            let member = BFieldMember::open(path, read_only, Murmur3)?;
+            let size = member.member_info().size;
+            if size != expected {
+                return Err(io::Error::from(BFieldError::Corrupt(format!(
+                    "{:?} has {} bits but array {} of the B-field should have {}",
+                    path,
+                    size,
+                    members.len(),
+                    expected
+                ))));
             }
+            members.push(member);
         }
-        self.members[pass].insert(key, value);
-        true
+        Ok(BField {
+            members,
+            read_only,
+            indeterminate_behavior: IndeterminateBehavior::default(),
+            mode: PhantomData,
+        })
     }
 
-    /// Returns the value of the given key if found, `None` otherwise.
-    /// The current implementation also returns `None` for indeterminate values.
-    pub fn get(&self, key: &[u8]) -> Option<BFieldVal> {
-        for secondary in self.members.iter() {
-            match secondary.get(key) {
-                BFieldLookup::Indeterminate => continue,
-                BFieldLookup::Some(value) => return Some(value),
-                BFieldLookup::None => return None,
-            }
-        }
-        // TODO: better value for totally indeterminate? panic?
-        // or return a Result<Option<BFieldVal>, ...> instead?
-        None
+    /// Loads a `BField` saved with `save_single`. The arrays are read into memory rather
+    /// than mapped; `persist_to_disk` would write them out as separate `.bfd` files
+    /// next to `path`.
+    pub fn open_single<P: AsRef<Path>>(path: P, read_only: bool) -> Result<Self, io::Error> {
+        Self::open_single_with_hasher(path, read_only, Murmur3)
     }
 
-    /// Get the info of each secondary array (`BFieldMember`) in the `BField`.
-    /// Returns `Vec<(size, n_hashes, marker_width, n_marker_bits)>`.
-    pub fn info(&self) -> Vec<(usize, u8, u8, u8)> {
-        self.members.iter().map(|m| m.info()).collect()
+    /// Loads a `BField` from a buffer returned by `to_bytes`, copying its arrays into
+    /// memory. `path` is only used to name the array files that `persist_to_disk`
+    /// would write, e.g. `bfield.bfs` gives `bfield.0.bfd`, `bfield.1.bfd`...
+    pub fn from_bytes<P: AsRef<Path>>(
+        path: P,
+        bytes: &[u8],
+        read_only: bool,
+    ) -> Result<Self, io::Error> {
+        Self::from_bytes_with_hasher(path, bytes, read_only, Murmur3)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Loads a `BField` saved with `save_compressed`.
+    ///
+    /// Compressed arrays can't be mapped in place, so they are all decompressed into
+    /// memory while loading: this trades disk space for a slower open and for memory
+    /// that can't be paged out like a mapped file. `persist_to_disk` would write them
+    /// out as separate, uncompressed `.bfd` files next to `path`.
+    #[cfg(feature = "zstd")]
+    pub fn open_compressed<P: AsRef<Path>>(path: P, read_only: bool) -> Result<Self, io::Error> {
+        Self::open_compressed_with_hasher(path, read_only, Murmur3)
+    }
 
-    #[test]
-    fn can_build_and_query_file_bfield() {
-        let tmp_dir = tempfile::tempdir().unwrap();
-        let n_secondaries = 4;
-        let bfield = BField::create(
-            tmp_dir.path(),
-            "bfield",
-            1_000_000,
-            10,
-            39,
-            4,
-            0.1,
-            0.025,
+    /// Same as `create` with `in_memory` set but without any array files, for tests,
+    /// benchmarks and short-lived `BField`s. The arrays get the same sizes as with
+    /// `create` and `other_params` is kept like with `create`.
+    ///
+    /// Since there are no files behind it, `persist_to_disk` fails; use `save_single`
+    /// or `to_bytes` to keep it around.
+    #[allow(clippy::too_many_arguments)]
+    pub fn in_memory(
+        size: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        n_secondaries: u8,
+        other_params: T,
+    ) -> Result<Self, io::Error> {
+        Self::create_with_naming(
+            |_| PathBuf::new(),
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
             n_secondaries,
-            false,
-            String::new(),
+            true,
+            0,
+            other_params,
         )
-        .expect("to build");
+    }
 
-        // Identity database
-        let max_value: u32 = 10_000;
-        for p in 0..n_secondaries {
-            for i in 0..max_value {
-                bfield.insert(&i.to_be_bytes().to_vec(), i, p as usize);
+    /// Builds an in-memory `BField` of `size` bits with the default parameters of
+    /// `BFieldBuilder` out of the given key/value pairs, going through all the passes.
+    /// Like with `in_memory` there are no array files behind it, so use `save_single`
+    /// or `to_bytes` to keep it around.
+    pub fn from_iter_in_memory<K, I>(
+        size: usize,
+        other_params: T,
+        iter: I,
+    ) -> Result<Self, io::Error>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = (K, BFieldVal)>,
+    {
+        let bfield = BFieldBuilder::with_other_params(size, other_params)
+            .in_memory(true)
+            .build_with_naming(|_| PathBuf::new())?;
+        let pairs: Vec<(K, BFieldVal)> = iter.into_iter().collect();
+        let items: Vec<(&[u8], BFieldVal)> = pairs
+            .iter()
+            .map(|(key, value)| (key.as_ref(), *value))
+            .collect();
+        for pass in 0..bfield.members.len() {
+            bfield
+                .insert_many(&items, pass)
+                .map_err(BFieldError::from)?;
+        }
+        Ok(bfield)
+    }
+
+    /// Builds a `BField` with the parameters of `builder` out of the lines of `reader`,
+    /// each turned into a key/value pair by `parse`, without holding the records in memory.
+    ///
+    /// `reader` is read twice: once to insert every record in the primary array, then
+    /// again to write the records that are indeterminate in it to a spill file next to
+    /// `path` (e.g. `/tmp/bfield.spill.1`). Each secondary array is then built from the
+    /// spill file of the previous one, which only holds a fraction β (`secondary_scaledown`)
+    /// of the records of the one before. The spill files are removed once the build is done.
+    pub fn build_from_reader<P, R, F>(
+        path: P,
+        builder: &BFieldBuilder<T>,
+        mut reader: R,
+        mut parse: F,
+    ) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+        R: BufRead + Seek,
+        F: FnMut(&str) -> Result<(Vec<u8>, BFieldVal), io::Error>,
+    {
+        let path = path.as_ref();
+        let bfield = builder.build(path)?;
+        let n_members = bfield.members.len();
+        let spill_files = SpillFiles {
+            path: path.to_path_buf(),
+            n_members,
+        };
+        let spill_path = |pass: usize| spill_files.path(pass);
+
+        let mut batch = Vec::with_capacity(STREAM_BATCH_SIZE);
+        for line in (&mut reader).lines() {
+            batch.push(parse(&line?)?);
+            if batch.len() == STREAM_BATCH_SIZE {
+                bfield.insert_records(&batch, 0)?;
+                batch.clear();
             }
         }
+        bfield.insert_records(&batch, 0)?;
+        batch.clear();
+        if n_members == 1 {
+            return Ok(bfield);
+        }
 
-        for i in 0..max_value {
-            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
-            assert_eq!(i, val);
+        reader.seek(SeekFrom::Start(0))?;
+        let mut spill = BufWriter::new(File::create(spill_path(1))?);
+        for line in reader.lines() {
+            let (key, value) = parse(&line?)?;
+            if bfield.members[0].get(&key) == BFieldLookup::Indeterminate {
+                write_record(&mut spill, &key, value)?;
+            }
         }
-        drop(bfield);
+        spill.flush()?;
+        drop(spill);
 
-        // and we can load them
-        let bfield = BField::<String>::load(&tmp_dir.path().join("bfield.0.bfd"), true).unwrap();
-        for i in 0..max_value {
-            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
-            assert_eq!(i, val);
+        for pass in 1..n_members {
+            let mut spill = BufReader::new(File::open(spill_path(pass))?);
+            while let Some(record) = read_record(&mut spill)? {
+                batch.push(record);
+                if batch.len() == STREAM_BATCH_SIZE {
+                    bfield.insert_records(&batch, pass)?;
+                    batch.clear();
+                }
+            }
+            bfield.insert_records(&batch, pass)?;
+            batch.clear();
+
+            if pass + 1 < n_members {
+                spill.seek(SeekFrom::Start(0))?;
+                let mut next_spill = BufWriter::new(File::create(spill_path(pass + 1))?);
+                while let Some((key, value)) = read_record(&mut spill)? {
+                    if bfield.members[pass].get(&key) == BFieldLookup::Indeterminate {
+                        write_record(&mut next_spill, &key, value)?;
+                    }
+                }
+                next_spill.flush()?;
+            }
+            drop(spill);
+            fs::remove_file(spill_path(pass))?;
         }
+        Ok(bfield)
+    }
+}
+
+/// The spill files of `build_from_reader` next to `path`, which are all removed when
+/// this is dropped so none are left behind when the build fails halfway.
+struct SpillFiles {
+    path: PathBuf,
+    n_members: usize,
+}
+
+impl SpillFiles {
+    /// The spill file holding the records to insert with the given `pass`
+    fn path(&self, pass: usize) -> PathBuf {
+        let mut spill_path = self.path.as_os_str().to_owned();
+        spill_path.push(format!(".spill.{pass}"));
+        PathBuf::from(spill_path)
+    }
+}
+
+impl Drop for SpillFiles {
+    fn drop(&mut self) {
+        // most are already gone once the build is done and there's no way to report a
+        // failure from here anyway
+        for pass in 1..self.n_members {
+            let _ = fs::remove_file(self.path(pass));
+        }
+    }
+}
+
+/// Checks that both scaledowns are in (0, 1], as needed by `member_sizes`.
+fn validate_scaledowns(secondary_scaledown: f64, max_scaledown: f64) -> Result<(), io::Error> {
+    for (name, scaledown) in &[
+        ("secondary_scaledown", secondary_scaledown),
+        ("max_scaledown", max_scaledown),
+    ] {
+        if !(*scaledown > 0. && *scaledown <= 1.) {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "{} is {} but needs to be in (0, 1]",
+                name, scaledown
+            ))));
+        }
+    }
+    Ok(())
+}
+
+/// Longest key made of segments that gets concatenated on the stack
+const SEGMENTS_STACK_LEN: usize = 256;
+
+/// Calls `f` with the concatenation of `segments`, only allocating if it doesn't fit in
+/// `SEGMENTS_STACK_LEN` bytes.
+fn with_concatenated<R>(segments: &[&[u8]], f: impl FnOnce(&[u8]) -> R) -> R {
+    let len: usize = segments.iter().map(|segment| segment.len()).sum();
+    if len > SEGMENTS_STACK_LEN {
+        return f(&segments.concat());
+    }
+    let mut buffer = [0u8; SEGMENTS_STACK_LEN];
+    let mut end = 0;
+    for segment in segments {
+        buffer[end..end + segment.len()].copy_from_slice(segment);
+        end += segment.len();
+    }
+    f(&buffer[..end])
+}
+
+/// Number of records `build_from_reader` inserts at once
+const STREAM_BATCH_SIZE: usize = 10_000;
+
+/// Writes a record to a `build_from_reader` spill file: the `u32` length of the key, the
+/// key and the `u64` value, big-endian.
+fn write_record<W: Write>(writer: &mut W, key: &[u8], value: BFieldVal) -> Result<(), io::Error> {
+    let key_len = u32::try_from(key.len()).map_err(|_| {
+        io::Error::from(BFieldError::InvalidParams(
+            "key longer than 4 GiB".to_string(),
+        ))
+    })?;
+    writer.write_all(&key_len.to_be_bytes())?;
+    writer.write_all(key)?;
+    writer.write_all(&value.to_be_bytes())
+}
+
+/// Reads back a record written by `write_record`, or `None` at the end of the file.
+fn read_record<R: BufRead>(reader: &mut R) -> Result<Option<(Vec<u8>, BFieldVal)>, io::Error> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut key_len = [0u8; 4];
+    reader.read_exact(&mut key_len)?;
+    let mut key = vec![0u8; u32::from_be_bytes(key_len) as usize];
+    reader.read_exact(&mut key)?;
+    let mut value = [0u8; 8];
+    reader.read_exact(&mut value)?;
+    Ok(Some((key, u64::from_be_bytes(value))))
+}
+
+/// Inserts the pairs in the primary array only, like calling `insert` with a `pass`
+/// of 0 for each of them, so keys that end up indeterminate are not propagated to the
+/// secondary arrays.
+///
+/// Panics if the `BField` is read-only or a value can't be encoded, see `insert`.
+impl<T, H, K> Extend<(K, BFieldVal)> for BField<T, H>
+where
+    T: Clone + DeserializeOwned + Serialize,
+    H: BFieldHasher,
+    K: AsRef<[u8]>,
+{
+    fn extend<I: IntoIterator<Item = (K, BFieldVal)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value, 0);
+        }
+    }
+}
+
+impl<T: Clone + DeserializeOwned + Serialize, H: BFieldHasher> BField<T, H> {
+    /// Same as `create_with_naming` but hashes keys with `hasher` rather than murmur3.
+    /// Its name is saved in the file headers so that the `BField` can't be loaded
+    /// with another hasher later on.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_with_hasher<F>(
+        naming: F,
+        size: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        n_secondaries: u8,
+        in_memory: bool,
+        seed: u64,
+        hasher: H,
+        other_params: T,
+    ) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        Self::create_with_placement(
+            naming,
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondary_scaledown,
+            max_scaledown,
+            n_secondaries,
+            in_memory,
+            seed,
+            false,
+            PositionScheme::default(),
+            hasher,
+            other_params,
+        )
+    }
+
+    /// Same as `create_with_hasher` but with `disjoint` markers each array is split into
+    /// `n_hashes` bands and the markers of a key go in a band each, so they never overlap;
+    /// see `BFieldBuilder::disjoint_markers`. Marker positions are derived from the hash
+    /// with `position_scheme`.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn create_with_placement<F>(
+        naming: F,
+        size: usize,
+        n_hashes: u8,             // k
+        marker_width: u8,         // nu
+        n_marker_bits: u8,        // kappa
+        secondary_scaledown: f64, // beta
+        max_scaledown: f64,
+        n_secondaries: u8,
+        in_memory: bool,
+        seed: u64,
+        disjoint: bool,
+        position_scheme: PositionScheme,
+        hasher: H,
+        other_params: T,
+    ) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        validate_scaledowns(secondary_scaledown, max_scaledown)?;
+        if n_secondaries == 0 {
+            return Err(io::Error::from(BFieldError::InvalidParams(
+                "n_secondaries needs to be at least 1".to_string(),
+            )));
+        }
+        let sizes = member_sizes(size, secondary_scaledown, max_scaledown, n_secondaries);
+        // check the smallest member up front so we don't leave some files behind
+        let smallest = sizes[sizes.len() - 1];
+        if smallest <= marker_width as usize {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "the smallest secondary array would be {} bits, which isn't more than marker_width ({})",
+                    smallest, marker_width
+                ))));
+        }
+        if disjoint && n_hashes > 0 && smallest / n_hashes as usize <= marker_width as usize {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "the smallest secondary array would be {} bits, which leaves no more than marker_width ({}) bits for each of the {} disjoint markers",
+                    smallest, marker_width, n_hashes
+                ))));
+        }
+
+        let mut members = Vec::new();
+        for (n, cur_size) in sizes.into_iter().enumerate() {
+            let file = naming(n);
+            let mut params = BFieldParams::new(n_hashes, marker_width, n_marker_bits, seed, None);
+            params.disjoint = disjoint;
+            params.position_scheme = position_scheme;
+            if n == 0 {
+                params.other = Some(other_params.clone());
+                params.secondaries = Some(SecondaryParams {
+                    secondary_scaledown,
+                    max_scaledown,
+                    n_secondaries,
+                });
+            }
+            let member = BFieldMember::create_with_params(
+                file,
+                in_memory,
+                cur_size,
+                params,
+                hasher.clone(),
+            )?;
+            members.push(member);
+        }
+
+        Ok(BField {
+            members,
+            read_only: false,
+            indeterminate_behavior: IndeterminateBehavior::default(),
+            mode: PhantomData,
+        })
+    }
+
+    /// Same as `create_from_config` for `BField`s hashing keys with `hasher`, whose name
+    /// has to match the one in `config`.
+    pub fn create_from_config_with_hasher<P: AsRef<Path>>(
+        path: P,
+        config: &BFieldConfig,
+        hasher: H,
+        other_params: T,
+    ) -> Result<Self, io::Error> {
+        let path = path.as_ref();
+        let filename = match path.file_name() {
+            Some(f) => f.to_string_lossy(),
+            None => {
+                return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "Couldn't get filename from {:?}",
+                    path
+                ))));
+            }
+        };
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        Self::from_config(
+            |n| directory.join(format!("{filename}.{n}.bfd")),
+            false,
+            config,
+            hasher,
+            Some(other_params),
+        )
+    }
+
+    /// Creates the arrays described by `config`, named by `naming` unless `in_memory`.
+    fn from_config<F>(
+        naming: F,
+        in_memory: bool,
+        config: &BFieldConfig,
+        hasher: H,
+        other_params: Option<T>,
+    ) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        if config.hasher != hasher.name() {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "the config is for the {} hasher but {} was given",
+                config.hasher,
+                hasher.name()
+            ))));
+        }
+        if config.sizes.is_empty() || config.sizes.len() > usize::from(u8::MAX) {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "the config has {} arrays but needs between 1 and 255",
+                config.sizes.len()
+            ))));
+        }
+
+        let mut members = Vec::new();
+        for (n, size) in config.sizes.iter().enumerate() {
+            let mut params = BFieldParams::new(
+                config.n_hashes,
+                config.marker_width,
+                config.n_marker_bits,
+                config.seed,
+                None,
+            );
+            params.fastrange = config.fastrange;
+            params.disjoint = config.disjoint;
+            params.position_scheme = config.position_scheme;
+            if n == 0 {
+                params.other = other_params.clone();
+                params.secondaries = config.secondaries;
+            }
+            let member = BFieldMember::create_with_params(
+                naming(n),
+                in_memory,
+                *size,
+                params,
+                hasher.clone(),
+            )?;
+            members.push(member);
+        }
+
+        Ok(BField {
+            members,
+            read_only: false,
+            indeterminate_behavior: IndeterminateBehavior::default(),
+            mode: PhantomData,
+        })
+    }
+
+    /// Same as `load_with_naming` for a `BField` built with `create_with_hasher`;
+    /// fails if it was built with a different hash function.
+    pub fn load_with_hasher<F>(naming: F, read_only: bool, hasher: H) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        let mut members = Vec::new();
+        loop {
+            let member_path = naming(members.len());
+            if !member_path.exists() {
+                break;
+            }
+            members.push(BFieldMember::open(&member_path, read_only, hasher.clone())?);
+        }
+
+        if members.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No Bfield found at {:?}", naming(0)),
+            ));
+        }
+        Ok(BField {
+            members,
+            read_only,
+            indeterminate_behavior: IndeterminateBehavior::default(),
+            mode: PhantomData,
+        })
+    }
+
+    /// Same as `open_single` for a `BField` built with `create_with_hasher`.
+    pub fn open_single_with_hasher<P: AsRef<Path>>(
+        path: P,
+        read_only: bool,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
+        let file = BufReader::new(File::open(path.as_ref())?);
+        Self::read_single(path, file, read_only, hasher)
+    }
+
+    /// Same as `from_bytes` for a `BField` built with `create_with_hasher`.
+    pub fn from_bytes_with_hasher<P: AsRef<Path>>(
+        path: P,
+        bytes: &[u8],
+        read_only: bool,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
+        Self::read_single(path, bytes, read_only, hasher)
+    }
+
+    /// Same as `open_compressed` for a `BField` built with `create_with_hasher`.
+    #[cfg(feature = "zstd")]
+    pub fn open_compressed_with_hasher<P: AsRef<Path>>(
+        path: P,
+        read_only: bool,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
+        let decoder = zstd::Decoder::new(File::open(path.as_ref())?)?;
+        Self::read_single(path, decoder, read_only, hasher)
+    }
+
+    /// Reads the single-file layout described in `save_single` from `file`, naming the
+    /// arrays after `path` like the files of `BFieldBuilder::build`. The arrays are read
+    /// in order so `file` doesn't need to be seekable.
+    fn read_single<P: AsRef<Path>, R: Read>(
+        path: P,
+        mut file: R,
+        read_only: bool,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
+        let path = path.as_ref();
+        let invalid = |msg: &str| io::Error::from(BFieldError::Corrupt(msg.to_string()));
+        // running out of bytes means the file was cut short, not that reading it failed
+        let truncated = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => invalid("truncated single-file B-field"),
+            _ => e,
+        };
+        let mut preamble = [0u8; SINGLE_PREAMBLE_LEN as usize];
+        file.read_exact(&mut preamble).map_err(truncated)?;
+        if preamble[..4] != SINGLE_MAGIC {
+            return Err(invalid("not a single-file B-field"));
+        }
+        let version = u16::from_be_bytes([preamble[4], preamble[5]]);
+        if version != SINGLE_VERSION {
+            return Err(invalid(&format!(
+                "unsupported single-file B-field version {} (expected {})",
+                version, SINGLE_VERSION
+            )));
+        }
+        let n_members = u16::from_be_bytes([preamble[6], preamble[7]]);
+        if n_members == 0 {
+            return Err(invalid("single-file B-field without any arrays"));
+        }
+
+        let mut toc = Vec::with_capacity(n_members as usize);
+        for _ in 0..n_members {
+            let mut entry = [0u8; SINGLE_TOC_ENTRY_LEN as usize];
+            file.read_exact(&mut entry).map_err(truncated)?;
+            let mut header_len = [0u8; 4];
+            let mut size = [0u8; 8];
+            let mut offset = [0u8; 8];
+            header_len.copy_from_slice(&entry[..4]);
+            size.copy_from_slice(&entry[4..12]);
+            offset.copy_from_slice(&entry[12..]);
+            toc.push((
+                u32::from_be_bytes(header_len) as usize,
+                u64::from_be_bytes(size) as usize,
+                u64::from_be_bytes(offset),
+            ));
+        }
+
+        let stem = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut members = Vec::with_capacity(toc.len());
+        let mut pos = SINGLE_PREAMBLE_LEN + SINGLE_TOC_ENTRY_LEN * u64::from(n_members);
+        for (n, (header_len, size, offset)) in toc.into_iter().enumerate() {
+            if offset < pos {
+                return Err(invalid("overlapping arrays in single-file B-field"));
+            }
+            let gap = offset - pos;
+            if io::copy(&mut (&mut file).take(gap), &mut io::sink())? != gap {
+                return Err(invalid("truncated single-file B-field"));
+            }
+            pos = offset + (header_len + size.div_ceil(8)) as u64;
+            let mut header = vec![0u8; header_len];
+            file.read_exact(&mut header).map_err(truncated)?;
+            let filename = path.with_file_name(format!("{stem}.{n}.bfd"));
+            members.push(
+                BFieldMember::from_parts(
+                    filename,
+                    &header,
+                    size,
+                    &mut file,
+                    read_only,
+                    hasher.clone(),
+                )
+                .map_err(truncated)?,
+            );
+        }
+        Ok(BField {
+            members,
+            read_only,
+            indeterminate_behavior: IndeterminateBehavior::default(),
+            mode: PhantomData,
+        })
+    }
+
+    /// Write the current `BField` to disk, along with a checksum of each array
+    /// for `verify`. Call it once done inserting, whether the `BField` was created
+    /// in memory or not.
+    pub fn persist_to_disk(mut self) -> Result<Self, io::Error> {
+        let mut members = Vec::with_capacity(self.members.len());
+        for m in std::mem::take(&mut self.members) {
+            members.push(m.persist_to_disk()?);
+        }
+        Ok(Self {
+            members,
+            read_only: self.read_only,
+            indeterminate_behavior: self.indeterminate_behavior,
+            mode: PhantomData,
+        })
+    }
+
+    /// ⚠️ Method for setting parameters without actually updating any files on disk. **Only useful for supporting legacy file formats
+    /// in which these parameters are not saved.**
+    pub fn mock_params(&mut self, params: T) {
+        self.members[0].params.other = Some(params);
+    }
+
+    /// Unsets every bit of every member so the `BField` can be reused, e.g. to run
+    /// several experiments with the same in-memory allocation.
+    /// Returns an error for read-only `BField`s.
+    pub fn clear(&mut self) -> Result<(), io::Error> {
+        if self.read_only {
+            return Err(io::Error::from(BFieldError::ReadOnly));
+        }
+        for member in self.members.iter_mut() {
+            member.clear()?;
+        }
+        Ok(())
+    }
+
+    /// Builds a new `BField` out of `keys` with a primary array of `new_size` bits and
+    /// otherwise the same parameters (hashes, markers, scaledowns, seed, hasher and
+    /// extra params) as this one, e.g. to compact a `BField` that ended up much larger
+    /// than needed once the actual number of keys is known, which makes lookups more
+    /// cache friendly.
+    ///
+    /// Keys can't be recovered from the arrays so they need to be supplied again, but
+    /// their values are looked up here; keys that are absent or indeterminate are left
+    /// out. `path` names the new array files like in `BFieldBuilder::build`.
+    pub fn rebuild_from_keys<P, K, I>(
+        &self,
+        path: P,
+        new_size: usize,
+        in_memory: bool,
+        keys: I,
+    ) -> Result<Self, io::Error>
+    where
+        P: AsRef<Path>,
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        let path = path.as_ref();
+        let filename = match path.file_name() {
+            Some(f) => f.to_string_lossy(),
+            None => {
+                return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "Couldn't get filename from {:?}",
+                    path
+                ))));
+            }
+        };
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        let primary = &self.members[0];
+        let other_params = primary.params.other.clone().ok_or_else(|| {
+            io::Error::from(BFieldError::Corrupt(
+                "the primary array has no params to rebuild with".to_string(),
+            ))
+        })?;
+        let (n_hashes, marker_width, n_marker_bits, sizes) = self.build_params();
+        // older files don't have them but the sizes of the arrays tell them apart
+        let secondaries = self.secondary_params().unwrap_or(SecondaryParams {
+            secondary_scaledown: sizes.get(1).map_or(1., |s| *s as f64 / sizes[0] as f64),
+            max_scaledown: sizes[sizes.len() - 1] as f64 / sizes[0] as f64,
+            n_secondaries: sizes.len() as u8,
+        });
+
+        let rebuilt = Self::create_with_placement(
+            |n| directory.join(format!("{filename}.{n}.bfd")),
+            new_size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            secondaries.secondary_scaledown,
+            secondaries.max_scaledown,
+            secondaries.n_secondaries,
+            in_memory,
+            primary.params.seed,
+            primary.params.disjoint,
+            primary.params.position_scheme,
+            primary.hasher.clone(),
+            other_params,
+        )?;
+        let pairs: Vec<(K, BFieldVal)> = keys
+            .into_iter()
+            .filter_map(|key| match self.lookup(&key) {
+                BFieldLookup::Some(value) => Some((key, value)),
+                _ => None,
+            })
+            .collect();
+        let items: Vec<(&[u8], BFieldVal)> = pairs
+            .iter()
+            .map(|(key, value)| (key.as_ref(), *value))
+            .collect();
+        for pass in 0..rebuilt.members.len() {
+            rebuilt
+                .insert_many(&items, pass)
+                .map_err(BFieldError::from)?;
+        }
+        Ok(rebuilt)
+    }
+
+    /// Grows the array of the given `pass` to `new_size` bits in place, e.g. when it
+    /// turned out too small halfway through a build, rather than rebuilding the whole
+    /// `BField` with `rebuild_from_keys`. Marker positions depend on the size of the
+    /// array so `keys` needs to hold every key inserted in it, see `BFieldMember::grow`.
+    /// The sizes in `secondary_params` no longer match the arrays afterwards.
+    pub fn grow<K, I>(&mut self, pass: usize, new_size: usize, keys: I) -> Result<(), io::Error>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        if self.read_only {
+            return Err(io::Error::from(BFieldError::ReadOnly));
+        }
+        self.check_pass(pass).map_err(BFieldError::from)?;
+        self.members[pass].grow(new_size, keys)
+    }
+
+    /// Appends a new array after the last one, e.g. when too many keys are still
+    /// indeterminate after the last pass, so that they can be inserted with the next `pass`
+    /// instead of rebuilding the whole `BField`.
+    ///
+    /// The array is created on disk at `path`, which should follow the naming of the other
+    /// arrays (e.g. `bfield.4.bfd` after `bfield.3.bfd`) for `load` to find it. It uses the
+    /// same seed and hasher as the others, and the `n_hashes`, `marker_width` and
+    /// `n_marker_bits` of `info` have to match theirs.
+    /// The `n_secondaries` of `secondary_params` counts the new array and is updated in
+    /// the primary array header right away. `load_with_paths` also checks the sizes of
+    /// the arrays, so `info.size` should then follow the scaledowns of the others.
+    pub fn push_member<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        info: MemberInfo,
+    ) -> Result<(), io::Error> {
+        if self.read_only {
+            return Err(io::Error::from(BFieldError::ReadOnly));
+        }
+        let primary = &self.members[0];
+        let existing = primary.member_info();
+        if (info.n_hashes, info.marker_width, info.n_marker_bits)
+            != (
+                existing.n_hashes,
+                existing.marker_width,
+                existing.n_marker_bits,
+            )
+        {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "the new array needs the same n_hashes ({}), marker_width ({}) and n_marker_bits ({}) as the others",
+                    existing.n_hashes, existing.marker_width, existing.n_marker_bits
+                ))));
+        }
+        let n_secondaries = u8::try_from(self.members.len() + 1).map_err(|_| {
+            io::Error::from(BFieldError::InvalidParams(
+                "a B-field can't have more than 255 arrays".to_string(),
+            ))
+        })?;
+
+        let mut params = BFieldParams::new(
+            info.n_hashes,
+            info.marker_width,
+            info.n_marker_bits,
+            primary.params.seed,
+            None,
+        );
+        params.disjoint = primary.params.disjoint;
+        params.position_scheme = primary.params.position_scheme;
+        let member = BFieldMember::create_with_params(
+            path,
+            false,
+            info.size,
+            params,
+            primary.hasher.clone(),
+        )?;
+        self.members.push(member);
+        if let Some(secondaries) = self.members[0].params.secondaries.as_mut() {
+            secondaries.n_secondaries = n_secondaries;
+        }
+        self.members[0].write_header()
+    }
+
+    /// Merges `other` into this `BField` by OR-ing their members' bits together, e.g. to
+    /// combine `BField`s built over disjoint sets of keys on separate machines.
+    ///
+    /// Both `BField`s need the same number of members with identical parameters (sizes,
+    /// hashes, marker encoding and seed), otherwise nothing is merged and an error is
+    /// returned. Note that merging sets more bits, so keys that were resolved in a member
+    /// of either `BField` may become indeterminate in the merged one (and were never
+    /// inserted in the next member), making the indeterminacy and false positive rates
+    /// higher than for a `BField` built over all keys at once.
+    pub fn merge(&mut self, other: &BField<T, H>) -> Result<(), MergeError> {
+        if self.read_only {
+            return Err(MergeError::ReadOnly);
+        }
+        if self.members.len() != other.members.len() {
+            return Err(MergeError::MemberCountMismatch {
+                expected: self.members.len(),
+                found: other.members.len(),
+            });
+        }
+        if !self
+            .members
+            .iter()
+            .zip(&other.members)
+            .all(|(member, other)| member.is_compatible(other))
+        {
+            return Err(MergeError::IncompatibleParams);
+        }
+        for (member, other) in self.members.iter_mut().zip(&other.members) {
+            member.merge(other)?;
+        }
+        Ok(())
+    }
+
+    /// ⚠️ Method for inserting a value into a `BField`
+    /// after it has been fully built and finalized.
+    /// **This method should be used with extreme care**
+    /// as it does not guarantee that keys are properly propagated
+    /// to secondary arrays and therefore may make lookups of previously
+    /// set values return an indeterminate result in the primary array,
+    /// then causing fallback to the secondary arrays where they were never
+    /// inserted (and returning a false negative).
+    ///
+    /// Panics if the `BField` is read-only or if `value` can't be encoded with the
+    /// `BField` parameters, like `insert`; see `force_insert_checked` for a
+    /// non-panicking version.
+    pub fn force_insert(&self, key: impl AsRef<[u8]>, value: BFieldVal) {
+        self.force_insert_checked(key, value)
+            .expect("Failed to insert into B-field");
+    }
+
+    /// Same as `force_insert` but reports what happened to the key, in particular
+    /// whether an existing different value had to be masked, or returns an error if the
+    /// value is out of range or the `BField` is read-only.
+    pub fn force_insert_checked(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: BFieldVal,
+    ) -> Result<ForceInsertOutcome, InsertError> {
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
+        self.force_insert_hashed(self.members[0].hash_key(key.as_ref()), value)
+    }
+
+    /// Same as `force_insert_checked` for many keys at once, e.g. to correct a whole
+    /// batch of keys after the build, hashing each key only once for all the members.
+    /// No key is inserted if any value is invalid.
+    ///
+    /// Returns how many keys ended up in each case along with the number of bits that
+    /// got set, which gives an idea of how many other keys were made indeterminate.
+    pub fn force_insert_many(
+        &self,
+        items: &[(&[u8], BFieldVal)],
+    ) -> Result<MaskStats, InsertError> {
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
+        let max = self.members[0].max_value();
+        if let Some((_, value)) = items.iter().find(|(_, value)| *value > max) {
+            return Err(InsertError::ValueOutOfRange { value: *value, max });
+        }
+        let bits_before = self.set_bits();
+        let mut stats = MaskStats::default();
+        for (key, value) in items {
+            match self.force_insert_hashed(self.members[0].hash_key(key), *value)? {
+                ForceInsertOutcome::Inserted => stats.inserted += 1,
+                ForceInsertOutcome::AlreadyPresent => stats.already_present += 1,
+                ForceInsertOutcome::Masked { inserted } => {
+                    stats.masked += 1;
+                    if inserted {
+                        stats.inserted += 1;
+                    } else {
+                        stats.indeterminate += 1;
+                    }
+                }
+                ForceInsertOutcome::Indeterminate => stats.indeterminate += 1,
+            }
+        }
+        stats.bits_set = self.set_bits() - bits_before;
+        Ok(stats)
+    }
+
+    /// "Removes" keys from the `BField` by masking their value in every member that
+    /// resolves them, so they come back absent (or indeterminate). Unlike
+    /// `force_insert_many` no new value is inserted.
+    ///
+    /// The same caveats as `force_insert` apply: every masked key sets more bits, which
+    /// can make other keys indeterminate; `MaskStats::bits_set` tells how many.
+    pub fn mask_many(&self, keys: &[&[u8]]) -> Result<MaskStats, InsertError> {
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
+        let bits_before = self.set_bits();
+        let mut stats = MaskStats::default();
+        for key in keys {
+            let hash = self.members[0].hash_key(key);
+            let mut masked = false;
+            let mut absent = false;
+            for secondary in &self.members {
+                match secondary.mask_hashed(hash)? {
+                    BFieldLookup::Some(_) => masked = true,
+                    BFieldLookup::None => {
+                        absent = true;
+                        break;
+                    }
+                    BFieldLookup::Indeterminate => {}
+                }
+            }
+            if masked {
+                stats.masked += 1;
+            } else if absent {
+                stats.absent += 1;
+            } else {
+                stats.indeterminate += 1;
+            }
+        }
+        stats.bits_set = self.set_bits() - bits_before;
+        Ok(stats)
+    }
+
+    fn force_insert_hashed(
+        &self,
+        hash: (u64, u64),
+        value: BFieldVal,
+    ) -> Result<ForceInsertOutcome, InsertError> {
+        let mut masked = false;
+        for secondary in &self.members {
+            let outcome = secondary.try_mask_or_insert_hashed(hash, value)?;
+            if outcome == MaskOutcome::Inserted {
+                self.members[0].add_inserted(1);
+            }
+            match outcome {
+                MaskOutcome::Inserted if masked => {
+                    return Ok(ForceInsertOutcome::Masked { inserted: true })
+                }
+                MaskOutcome::Inserted => return Ok(ForceInsertOutcome::Inserted),
+                MaskOutcome::AlreadyPresent => return Ok(ForceInsertOutcome::AlreadyPresent),
+                MaskOutcome::Masked => masked = true,
+                MaskOutcome::Indeterminate => {}
+            }
+        }
+        if masked {
+            Ok(ForceInsertOutcome::Masked { inserted: false })
+        } else {
+            Ok(ForceInsertOutcome::Indeterminate)
+        }
+    }
+
+    /// Insert the given key/value at the given pass (1-indexed `BField` array/member).
+    /// Returns whether the value was inserted during this call, i.e., will return `false` if
+    /// the value was already present.
+    ///
+    /// Panics if the `BField` is read-only, if `pass` isn't below `n_members` or if `value`
+    /// can't be encoded with the `BField` parameters; see `try_insert` for a non-panicking
+    /// version.
+    pub fn insert(&self, key: impl AsRef<[u8]>, value: BFieldVal, pass: usize) -> bool {
+        self.try_insert(key, value, pass)
+            .expect("Failed to insert into B-field")
+    }
+
+    /// Same as `insert` for `BField`s where 0 is reserved as a sentinel, so it can't be
+    /// inserted by mistake; see `get_nonzero`.
+    pub fn insert_nonzero(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: NonZeroBFieldVal,
+        pass: usize,
+    ) -> bool {
+        self.insert(key, value.get(), pass)
+    }
+
+    /// Same as `insert` but returns an `InsertError` instead of panicking if the `BField`
+    /// is read-only, if `pass` is out of range or if `value` is larger than the maximum
+    /// encodable value.
+    pub fn try_insert(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: BFieldVal,
+        pass: usize,
+    ) -> Result<bool, InsertError> {
+        self.check_pass(pass)?;
+        let key = key.as_ref();
+        if pass > 0 {
+            for secondary in self.members[..pass].iter() {
+                match secondary.get(key) {
+                    BFieldLookup::Indeterminate => continue,
+                    _ => return Ok(false),
+                }
+            }
+        }
+        self.members[pass].try_insert(key, value)?;
+        if pass == 0 {
+            self.members[0].add_inserted(1);
+        }
+        Ok(true)
+    }
+
+    /// Same as `try_insert` for a key that was already hashed, see `lookup_prehashed`
+    /// for what `hash` must be.
+    pub fn try_insert_prehashed(
+        &self,
+        hash: (u64, u64),
+        value: BFieldVal,
+        pass: usize,
+    ) -> Result<bool, InsertError> {
+        self.check_pass(pass)?;
+        if pass > 0 {
+            for secondary in self.members[..pass].iter() {
+                match secondary.get_prehashed(hash) {
+                    BFieldLookup::Indeterminate => continue,
+                    _ => return Ok(false),
+                }
+            }
+        }
+        self.members[pass].insert_prehashed(hash, value)?;
+        if pass == 0 {
+            self.members[0].add_inserted(1);
+        }
+        Ok(true)
+    }
+
+    /// Same as `try_insert` but returns what `lookup` would have returned for the key
+    /// just before the insert, to detect collisions or values changing without a
+    /// separate lookup. Like `try_insert`, nothing is inserted if an array before
+    /// `pass` already resolves the key, and its value is returned.
+    pub fn replace(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: BFieldVal,
+        pass: usize,
+    ) -> Result<BFieldLookup, InsertError> {
+        self.check_pass(pass)?;
+        let key = key.as_ref();
+        for (ix, member) in self.members.iter().enumerate() {
+            let lookup = if ix == pass {
+                let previous = member.replace(key, value)?;
+                if pass == 0 {
+                    member.add_inserted(1);
+                }
+                previous
+            } else {
+                member.get(key)
+            };
+            if lookup != BFieldLookup::Indeterminate {
+                return Ok(lookup);
+            }
+        }
+        Ok(BFieldLookup::Indeterminate)
+    }
+
+    /// Same as `try_insert` but only writes to the member if the key doesn't already
+    /// have `value` there, which saves rewriting bits when rebuilding a `BField` with
+    /// mostly unchanged keys. Keys that are already present aren't counted again in `len`.
+    ///
+    /// Returns whether the marker was written during this call.
+    pub fn insert_if_absent(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: BFieldVal,
+        pass: usize,
+    ) -> Result<bool, InsertError> {
+        self.check_pass(pass)?;
+        let key = key.as_ref();
+        if pass > 0 {
+            for secondary in self.members[..pass].iter() {
+                match secondary.get(key) {
+                    BFieldLookup::Indeterminate => continue,
+                    _ => return Ok(false),
+                }
+            }
+        }
+        let inserted = self.members[pass].insert_if_absent(key, value)?;
+        if inserted && pass == 0 {
+            self.members[0].add_inserted(1);
+        }
+        Ok(inserted)
+    }
+
+    /// Same as `try_insert` but sets the marker bits with atomic operations, so several
+    /// threads can insert into the same pass at once (see the `BField` docs for what
+    /// can't run alongside it). Slightly slower than `try_insert` on a single thread.
+    pub fn try_insert_atomic(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: BFieldVal,
+        pass: usize,
+    ) -> Result<bool, InsertError> {
+        self.check_pass(pass)?;
+        let key = key.as_ref();
+        if pass > 0 {
+            for secondary in self.members[..pass].iter() {
+                match secondary.get(key) {
+                    BFieldLookup::Indeterminate => continue,
+                    _ => return Ok(false),
+                }
+            }
+        }
+        self.members[pass].try_insert_atomic(key, value)?;
+        if pass == 0 {
+            self.members[0].add_inserted(1);
+        }
+        Ok(true)
+    }
+
+    /// Same as `insert` for a key made of several `segments` (e.g. a prefix and a k-mer),
+    /// which hashes like their concatenation but without allocating for short keys.
+    pub fn insert_segments(&self, segments: &[&[u8]], value: BFieldVal, pass: usize) -> bool {
+        with_concatenated(segments, |key| self.insert(key, value, pass))
+    }
+
+    /// Insert all the given key/value pairs at the given pass, like calling `try_insert`
+    /// for each of them but faster for large batches since hashing and memory accesses
+    /// are pipelined.
+    /// Returns how many of the pairs were inserted during this call, i.e. the ones not
+    /// already resolved by an earlier member. No pair is inserted if any value is invalid.
+    pub fn insert_many(
+        &self,
+        items: &[(&[u8], BFieldVal)],
+        pass: usize,
+    ) -> Result<usize, InsertError> {
+        self.check_pass(pass)?;
+        if pass == 0 {
+            self.members[0].insert_many(items)?;
+            self.members[0].add_inserted(items.len() as u64);
+            return Ok(items.len());
+        }
+        let indeterminate: Vec<(&[u8], BFieldVal)> = items
+            .iter()
+            .filter(|(key, _)| {
+                self.members[..pass]
+                    .iter()
+                    .all(|secondary| secondary.get(key) == BFieldLookup::Indeterminate)
+            })
+            .cloned()
+            .collect();
+        self.members[pass].insert_many(&indeterminate)?;
+        Ok(indeterminate.len())
+    }
+
+    /// Inserts owned records in the given array, the records of later passes being
+    /// known to be indeterminate in the earlier arrays already.
+    fn insert_records(
+        &self,
+        records: &[(Vec<u8>, BFieldVal)],
+        pass: usize,
+    ) -> Result<(), io::Error> {
+        let items: Vec<(&[u8], BFieldVal)> = records
+            .iter()
+            .map(|(key, value)| (&key[..], *value))
+            .collect();
+        let res = if pass == 0 {
+            self.insert_many(&items, 0).map(|_| ())
+        } else {
+            self.members[pass].insert_many(&items)
+        };
+        res.map_err(|e| BFieldError::from(e).into())
+    }
+
+    /// Checks the `BField` can be inserted into at the given pass.
+    fn check_pass(&self, pass: usize) -> Result<(), InsertError> {
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
+        if pass >= self.members.len() {
+            return Err(InsertError::InvalidPass {
+                pass,
+                n_members: self.members.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Builds the whole `BField` from the given key/value pairs using all the
+    /// threads of the rayon thread pool.
+    ///
+    /// Each pass is done one after the other like with `insert_many`, but the
+    /// keys of a pass are split across threads which write their markers into the
+    /// member with atomic bit operations, so concurrent writes to the same bytes are
+    /// safe. Taking `&mut self` guarantees nothing else inserts (non-atomically) while
+    /// the build is running.
+    #[cfg(feature = "rayon")]
+    pub fn par_build(&mut self, items: &[(&[u8], BFieldVal)]) -> Result<(), InsertError>
+    where
+        T: Sync,
+        H: Sync,
+    {
+        use rayon::prelude::*;
+
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
+        for pass in 0..self.members.len() {
+            if pass == 0 {
+                self.members[0].par_insert_many(items)?;
+                self.members[0].add_inserted(items.len() as u64);
+                continue;
+            }
+            let indeterminate: Vec<(&[u8], BFieldVal)> = items
+                .par_iter()
+                .filter(|(key, _)| {
+                    self.members[..pass]
+                        .iter()
+                        .all(|secondary| secondary.get(key) == BFieldLookup::Indeterminate)
+                })
+                .cloned()
+                .collect();
+            self.members[pass].par_insert_many(&indeterminate)?;
+        }
+        Ok(())
+    }
+
+    /// Makes sure every insert so far is on disk, e.g. before copying the files or
+    /// exiting, rather than relying on the OS writing the mappings back at some point
+    /// after they're dropped. This also saves the insert count of `len` in the primary
+    /// array header, which dropping the `BField` does as well, but not the checksum,
+    /// which only `persist_to_disk` updates.
+    pub fn flush(&self) -> Result<(), io::Error> {
+        for member in &self.members {
+            member.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Turns the `BField` into a `ReadOnlyBField`, which has no methods to insert at all,
+    /// e.g. once it's built and about to be shared with code that should only query it.
+    pub fn into_read_only(mut self) -> ReadOnlyBField<T, H> {
+        BField {
+            members: std::mem::take(&mut self.members),
+            read_only: true,
+            indeterminate_behavior: self.indeterminate_behavior,
+            mode: PhantomData,
+        }
+    }
+}
+
+/// The methods that only read the arrays, which `ReadOnlyBField` has too.
+impl<T: Clone + DeserializeOwned + Serialize, H: BFieldHasher, M> BField<T, H, M> {
+    /// Saves all the arrays of the `BField` into the single file at `path`, which is easier
+    /// to ship around than one file per array. It can be read back with `open_single`.
+    ///
+    /// The file starts with the `BFLD` magic bytes, a `u16` format version and the `u16`
+    /// number of arrays, followed by a table of contents with a `u32` header length, the
+    /// `u64` size in bits and the `u64` offset of each array, all big-endian. Each array
+    /// is stored at its offset as its header followed by its bits.
+    pub fn save_single<P: AsRef<Path>>(&self, path: P) -> Result<(), io::Error> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_single(&mut file)?;
+        file.flush()
+    }
+
+    /// Returns the whole `BField` as a self-contained buffer, e.g. to upload it to object
+    /// storage or move it to a machine without mmap. The layout is the same as the file
+    /// written by `save_single` and it can be read back with `from_bytes`.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, io::Error> {
+        let mut bytes = Vec::new();
+        self.write_single(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Writes the single-file layout described in `save_single` to `file`.
+    fn write_single<W: Write>(&self, file: &mut W) -> Result<(), io::Error> {
+        let parts = self
+            .members
+            .iter()
+            .map(|m| m.to_parts())
+            .collect::<Result<Vec<_>, _>>()?;
+        file.write_all(&SINGLE_MAGIC)?;
+        file.write_all(&SINGLE_VERSION.to_be_bytes())?;
+        file.write_all(&(parts.len() as u16).to_be_bytes())?;
+
+        let mut offset = SINGLE_PREAMBLE_LEN + SINGLE_TOC_ENTRY_LEN * parts.len() as u64;
+        for (member, (header, bytes)) in self.members.iter().zip(&parts) {
+            file.write_all(&(header.len() as u32).to_be_bytes())?;
+            file.write_all(&(member.member_info().size as u64).to_be_bytes())?;
+            file.write_all(&offset.to_be_bytes())?;
+            offset += (header.len() + bytes.len()) as u64;
+        }
+        for (header, bytes) in &parts {
+            file.write_all(header)?;
+            file.write_all(bytes)?;
+        }
+        Ok(())
+    }
+
+    /// Saves the `BField` like `save_single` but compressed with zstd at the given
+    /// `level` (0 picks zstd's default), which shrinks prebuilt indices a lot when the
+    /// arrays aren't saturated yet. Read it back with `open_compressed`.
+    #[cfg(feature = "zstd")]
+    pub fn save_compressed<P: AsRef<Path>>(&self, path: P, level: i32) -> Result<(), io::Error> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = zstd::Encoder::new(file, level)?;
+        self.write_single(&mut encoder)?;
+        encoder.finish()?.flush()
+    }
+
+    /// Checks the bits of every array against the checksum saved by the last
+    /// `persist_to_disk`; any insert since then makes this fail.
+    pub fn verify(&self) -> Result<(), ChecksumError> {
+        self.members.iter().try_for_each(|m| m.verify())
+    }
+
+    /// Checks that a sample of the values that can be inserted round trip exactly
+    /// through the markers of every array, returning an `InvalidData` error otherwise,
+    /// e.g. for an `n_marker_bits` too high to be encoded. It's cheap but builds the
+    /// marker tables even for a read-only `BField`, so it's left to callers to run it
+    /// right after `create` or `load`.
+    pub fn check_encoding(&self) -> Result<(), io::Error> {
+        self.members.iter().try_for_each(|m| m.check_encoding())
+    }
+
+    /// Returns a copy of the `BField` held in memory, e.g. to try out inserts without
+    /// touching the original. The copy can always be inserted into, even if the
+    /// original was loaded as `read_only`, but keeps its file names: calling
+    /// `persist_to_disk` on it overwrites the files of the original.
+    pub fn try_clone(&self) -> Result<BField<T, H>, io::Error> {
+        let members = self
+            .members
+            .iter()
+            .map(|m| m.try_clone())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(BField {
+            members,
+            read_only: false,
+            indeterminate_behavior: self.indeterminate_behavior,
+            mode: PhantomData,
+        })
+    }
+
+    /// Returns `(n_hashes, marker_width, n_marker_bits, Vec<size of each member>)`.
+    pub fn build_params(&self) -> (u8, u8, u8, Vec<usize>) {
+        let (_, n_hashes, marker_width, n_marker_bits) = self.members[0].info();
+        let sizes = self.members.iter().map(|i| i.info().0).collect();
+        (n_hashes, marker_width, n_marker_bits, sizes)
+    }
+
+    /// Returns the sizes and parameters of the arrays, which `create_from_config` can
+    /// build an empty `BField` from again. It can be serialized to TOML or JSON to keep
+    /// alongside the arrays.
+    pub fn config(&self) -> BFieldConfig {
+        let primary = &self.members[0];
+        let (n_hashes, marker_width, n_marker_bits, sizes) = self.build_params();
+        BFieldConfig {
+            sizes,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            seed: primary.params.seed,
+            hasher: primary.params.hasher.clone(),
+            fastrange: primary.params.fastrange,
+            disjoint: primary.params.disjoint,
+            position_scheme: primary.params.position_scheme,
+            secondaries: self.secondary_params(),
+        }
+    }
+
+    /// Returns the `secondary_scaledown`, `max_scaledown` and `n_secondaries` the `BField`
+    /// was created with, or `None` for files written by older versions that didn't save them.
+    pub fn secondary_params(&self) -> Option<SecondaryParams> {
+        self.members[0].params.secondaries
+    }
+
+    /// Returns the params given at build time to the `BField` arrays.
+    pub fn params(&self) -> &Option<T> {
+        &self.members[0].params.other
+    }
+
+    /// Same as `get` for a key made of several `segments`, see `insert_segments`.
+    pub fn get_segments(&self, segments: &[&[u8]]) -> Option<BFieldVal> {
+        with_concatenated(segments, |key| self.get(key))
+    }
+
+    /// Returns the value of the given key if found, `None` otherwise.
+    /// Keys that are indeterminate in every member also return `None` unless another
+    /// `IndeterminateBehavior` is set; use `get_checked` to tell those apart.
+    ///
+    /// 0 is a value like any other: a key inserted with it returns `Some(0)`, never
+    /// `None`, so check for `None` rather than for a 0 value (e.g. with `unwrap_or(0)`)
+    /// to find absent keys. `get_nonzero` is there for when 0 is used as a sentinel.
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Option<BFieldVal> {
+        self.resolve(self.lookup(key))
+    }
+
+    /// Same as `get` for `BField`s where 0 is reserved as a sentinel rather than a
+    /// value, e.g. taxonomy IDs where 0 means unassigned: keys inserted with 0 return
+    /// `None` like absent ones.
+    pub fn get_nonzero(&self, key: impl AsRef<[u8]>) -> Option<NonZeroBFieldVal> {
+        self.get(key).and_then(NonZeroBFieldVal::new)
+    }
+
+    /// Returns whether the value of the given key is between `lo` and `hi` (inclusive),
+    /// e.g. to check which bucket of IDs it belongs to, or `None` whenever `get` would.
+    pub fn get_in_range(
+        &self,
+        key: impl AsRef<[u8]>,
+        lo: BFieldVal,
+        hi: BFieldVal,
+    ) -> Option<bool> {
+        self.get(key).map(|value| (lo..=hi).contains(&value))
+    }
+
+    /// Applies the `IndeterminateBehavior` to the result of a lookup.
+    #[inline]
+    fn resolve(&self, lookup: BFieldLookup) -> Option<BFieldVal> {
+        match lookup {
+            BFieldLookup::Some(value) => Some(value),
+            BFieldLookup::None => None,
+            BFieldLookup::Indeterminate => match self.indeterminate_behavior {
+                IndeterminateBehavior::ReturnNone | IndeterminateBehavior::ReturnErr => None,
+                IndeterminateBehavior::Panic => {
+                    panic!("B-field key is indeterminate in every member")
+                }
+            },
+        }
+    }
+
+    /// Same as `get` but returns `Err(Indeterminate)` for keys indeterminate in every
+    /// member when the `IndeterminateBehavior` is `ReturnErr`. The other behaviors
+    /// apply as for `get`, so this never errors with the default `ReturnNone`.
+    pub fn try_get(&self, key: impl AsRef<[u8]>) -> Result<Option<BFieldVal>, Indeterminate> {
+        match self.lookup(key) {
+            BFieldLookup::Indeterminate
+                if self.indeterminate_behavior == IndeterminateBehavior::ReturnErr =>
+            {
+                Err(Indeterminate)
+            }
+            lookup => Ok(self.resolve(lookup)),
+        }
+    }
+
+    /// Looks up the given key in each array in turn until one resolves it, returning
+    /// `BFieldLookup::Indeterminate` only if every array was indeterminate for it.
+    /// This is the same as `get_checked` as an enum.
+    pub fn lookup(&self, key: impl AsRef<[u8]>) -> BFieldLookup {
+        let key = key.as_ref();
+        for secondary in self.members.iter() {
+            match secondary.get(key) {
+                BFieldLookup::Indeterminate => continue,
+                resolved => return resolved,
+            }
+        }
+        BFieldLookup::Indeterminate
+    }
+
+    /// Same as `lookup` for a key that was already hashed, to avoid hashing it again when
+    /// the hash is also used elsewhere, e.g. by a Bloom filter in front of the `BField`.
+    ///
+    /// `hash` must be what the `BField`'s hasher returns for the key with `seed()`, i.e.
+    /// `murmurhash3_x64_128(key, seed)` unless it was built with `create_with_hasher`.
+    /// Any other hash just looks up (or inserts) another key, without any error.
+    pub fn lookup_prehashed(&self, hash: (u64, u64)) -> BFieldLookup {
+        for secondary in self.members.iter() {
+            match secondary.get_prehashed(hash) {
+                BFieldLookup::Indeterminate => continue,
+                resolved => return resolved,
+            }
+        }
+        BFieldLookup::Indeterminate
+    }
+
+    /// Returns the value of the given key if found, `None` if it is absent and
+    /// `Err(Indeterminate)` if every member was indeterminate for that key.
+    /// A high rate of `Indeterminate` usually means the `BField` is undersized.
+    pub fn get_checked(&self, key: impl AsRef<[u8]>) -> Result<Option<BFieldVal>, Indeterminate> {
+        match self.lookup(key) {
+            BFieldLookup::Indeterminate => Err(Indeterminate),
+            BFieldLookup::Some(value) => Ok(Some(value)),
+            BFieldLookup::None => Ok(None),
+        }
+    }
+
+    /// Returns the seed keys are hashed with, to hash them up front for
+    /// `lookup_prehashed` and `try_insert_prehashed`.
+    pub fn seed(&self) -> u64 {
+        self.members[0].params.seed
+    }
+
+    /// Returns the hash the `BField` computes for `key` with its hasher and `seed()`,
+    /// i.e. `murmurhash3_x64_128(key, seed)` unless it was built with
+    /// `create_with_hasher`. It's the same for every array, so companion structures
+    /// (prefilters, shards) can bucket keys consistently with the `BField`, and pass
+    /// it on to `lookup_prehashed` and `try_insert_prehashed`.
+    pub fn hash_key(&self, key: impl AsRef<[u8]>) -> (u64, u64) {
+        self.members[0].hash_key(key.as_ref())
+    }
+
+    /// Experimental: returns the candidate values of a key that was inserted into the
+    /// primary array several times with different values, e.g. the set of taxa a k-mer
+    /// belongs to. Inserts OR the markers together so this returns every value whose
+    /// marker is part of the result, which includes values that were never inserted:
+    /// two values with disjoint markers of 4 bits already decode to 70 candidates. It
+    /// also saturates the array much faster, so keep the sets small.
+    ///
+    /// Only the primary array is read since secondary arrays only get keys that are
+    /// indeterminate in the earlier ones.
+    pub fn get_multi(&self, key: impl AsRef<[u8]>) -> Vec<BFieldVal> {
+        self.members[0].get_multi(key.as_ref())
+    }
+
+    /// Same as `get` but also returns the index of the member that resolved the key,
+    /// which shows how often lookups fall through to the secondary arrays.
+    pub fn get_with_member(&self, key: impl AsRef<[u8]>) -> Option<(BFieldVal, usize)> {
+        let key = key.as_ref();
+        for (ix, secondary) in self.members.iter().enumerate() {
+            match secondary.get(key) {
+                BFieldLookup::Indeterminate => continue,
+                BFieldLookup::Some(value) => return Some((value, ix)),
+                BFieldLookup::None => return None,
+            }
+        }
+        None
+    }
+
+    /// Same as `get` but also returns the number of bits set in the merged marker of the
+    /// key in the primary array, to tell how clean a hit was. It's `n_marker_bits` when
+    /// the primary array resolved the key and more when it was indeterminate there and a
+    /// secondary array resolved it instead, i.e. the key is borderline in the primary.
+    pub fn get_detailed(&self, key: impl AsRef<[u8]>) -> Option<(BFieldVal, u32)> {
+        let key = key.as_ref();
+        let (marker, popcount) = self.members[0].get_raw_marker(key);
+        let lookup = match self.members[0].decode(marker) {
+            BFieldLookup::Indeterminate => self.members[1..]
+                .iter()
+                .map(|secondary| secondary.get(key))
+                .find(|lookup| *lookup != BFieldLookup::Indeterminate)
+                .unwrap_or(BFieldLookup::Indeterminate),
+            resolved => resolved,
+        };
+        self.resolve(lookup).map(|value| (value, popcount))
+    }
+
+    /// Returns the merged marker of `key` in each member along with its number of set
+    /// bits, which is handy to diagnose why a key comes back indeterminate: a value needs
+    /// exactly `n_marker_bits` bits set, anything above that is indeterminate.
+    pub fn get_raw_markers(&self, key: impl AsRef<[u8]>) -> Vec<(u128, u32)> {
+        self.members
+            .iter()
+            .map(|m| m.get_raw_marker(key.as_ref()))
+            .collect()
+    }
+
+    /// Returns whether the given key is present, i.e. `true` as soon as a member
+    /// resolves it to a value. Behaves exactly like `self.get(key).is_some()`.
+    pub fn contains_key(&self, key: impl AsRef<[u8]>) -> bool {
+        self.resolve(self.lookup(key)).is_some()
+    }
+
+    /// Returns the values of all the given keys, in order, with the same semantics as `get`.
+    ///
+    /// Keys are hashed up front and their marker positions prefetched ahead of being
+    /// read, which hides a good part of the memory latency compared to calling `get` in
+    /// a loop. Only the keys indeterminate in a member are looked up in the next one.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Vec<Option<BFieldVal>> {
+        let mut values = vec![BFieldLookup::Indeterminate; keys.len()];
+        let mut pending: Vec<usize> = (0..keys.len()).collect();
+        for secondary in self.members.iter() {
+            if pending.is_empty() {
+                break;
+            }
+            let pending_keys: Vec<&[u8]> = pending.iter().map(|ix| keys[*ix]).collect();
+            let lookups = secondary.get_many(&pending_keys);
+            pending = pending
+                .into_iter()
+                .zip(lookups)
+                .filter_map(|(ix, lookup)| match lookup {
+                    BFieldLookup::Indeterminate => Some(ix),
+                    resolved => {
+                        values[ix] = resolved;
+                        None
+                    }
+                })
+                .collect();
+        }
+        values
+            .into_iter()
+            .map(|lookup| self.resolve(lookup))
+            .collect()
+    }
+
+    /// Same as `get_many` but splits the keys into chunks looked up on the threads of the
+    /// rayon thread pool. A shared `&BField` can also be queried from rayon directly
+    /// (e.g. `keys.par_iter().map(|key| bfield.get(key))`) since it's `Sync`, but this
+    /// keeps the prefetching of `get_many` within each chunk.
+    ///
+    /// Lookups spend most of their time waiting on memory, so throughput stops scaling
+    /// once the threads saturate the memory bandwidth (or the disk, for arrays that
+    /// aren't in the page cache), which usually happens well before every core is busy.
+    #[cfg(feature = "rayon")]
+    pub fn par_get_many(&self, keys: &[&[u8]]) -> Vec<Option<BFieldVal>>
+    where
+        T: Sync,
+        H: Sync,
+    {
+        use rayon::prelude::*;
+
+        keys.par_chunks(PAR_GET_CHUNK)
+            .flat_map_iter(|chunk| self.get_many(chunk))
+            .collect()
+    }
+
+    /// Returns the number of keys inserted in the primary array with `insert`,
+    /// `insert_many` or `par_build`, plus the new keys added by `force_insert`.
+    /// Inserting the same key twice counts it twice.
+    ///
+    /// The count is saved in the primary array header by `flush`, `persist_to_disk` and
+    /// when a writable `BField` built on disk is dropped, so it's still there once loaded.
+    pub fn len(&self) -> u64 {
+        self.members[0].n_inserted()
+    }
+
+    /// Returns whether no key was inserted, see `len`.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the fill ratio (the fraction of bits set) of each member, which shows how
+    /// close they are to saturation, e.g. to check whether `secondary_scaledown` and
+    /// `max_scaledown` left enough headroom.
+    pub fn saturation(&self) -> Vec<f64> {
+        self.members.iter().map(|m| m.fill_ratio()).collect()
+    }
+
+    /// Returns the `popcount_histogram` of each member, i.e. how many `marker_width`-bit
+    /// windows have 0, 1, ..., 128 bits set, to see how close markers are to becoming
+    /// indeterminate when tuning `marker_width` and `n_marker_bits`. Pass a `sample` size
+    /// to only read that many windows of each array instead of all of them.
+    pub fn popcount_histograms(&self, sample: Option<usize>) -> Vec<[u64; 129]> {
+        self.members
+            .iter()
+            .map(|m| m.popcount_histogram(sample))
+            .collect()
+    }
+
+    /// Yields the position and decoded value of every window of the array of the given
+    /// `pass` with exactly `n_marker_bits` bits set, checking every bit offset.
+    ///
+    /// This is meant for offline analysis, e.g. of corrupted files: keys can't be
+    /// recovered, and many results are false markers made of bits from overlapping or
+    /// neighbouring keys. It reads the whole array.
+    ///
+    /// Panics if `pass` isn't below `n_members`.
+    pub fn iter_markers(&self, pass: usize) -> impl Iterator<Item = (usize, BFieldVal)> + '_ {
+        self.members[pass].iter_markers()
+    }
+
+    /// Returns the number of bits set in `range` of the array of the given `pass`, e.g.
+    /// to look at how the bits are spread for occupancy analyses.
+    ///
+    /// Panics if `pass` isn't below `n_members` or `range` goes past the array.
+    pub fn rank(&self, pass: usize, range: std::ops::Range<usize>) -> usize {
+        self.members[pass].rank(range)
+    }
+
+    /// Returns the position of the `n`th (from 0) bit set at or after `start` in the
+    /// array of the given `pass`, or `None` if there are fewer bits set.
+    ///
+    /// Panics if `pass` isn't below `n_members`.
+    pub fn select(&self, pass: usize, n: usize, start: usize) -> Option<usize> {
+        self.members[pass].select(n, start)
+    }
+
+    /// Returns a one-line description of the `BField` for people, e.g. for a CLI to print:
+    /// its total size, the size, parameters and fill ratio of each array and the
+    /// memory it takes. Computing the fill ratios reads every array.
+    pub fn summary(&self) -> String {
+        let info = self.member_info();
+        let total_bits: usize = info.iter().map(|i| i.size).sum();
+        let arrays: Vec<String> = info
+            .iter()
+            .zip(self.saturation())
+            .enumerate()
+            .map(|(ix, (info, fill_ratio))| {
+                format!("#{} {} bits {:.1}% full", ix, info.size, fill_ratio * 100.)
+            })
+            .collect();
+        let first = info[0];
+        format!(
+            "B-field with {} arrays of {} bits in total ({} bytes with headers), {} hashes, \
+             {}-bit markers with {} bits set: {}",
+            info.len(),
+            total_bits,
+            self.memory_bytes(),
+            first.n_hashes,
+            first.marker_width,
+            first.n_marker_bits,
+            arrays.join(", ")
+        )
+    }
+
+    /// Returns an estimate of the number of bytes taken by the `BField`, i.e. the bit
+    /// arrays of all the members along with their headers.
+    pub fn memory_bytes(&self) -> usize {
+        self.members.iter().map(|m| m.memory_bytes()).sum()
+    }
+
+    /// Returns the number of bits set across all the members.
+    fn set_bits(&self) -> u64 {
+        self.members.iter().map(|m| m.set_bits()).sum()
+    }
+
+    /// Sets what `get`, `try_get`, `get_many` and `contains_key` do with keys that are
+    /// indeterminate in every member, `IndeterminateBehavior::ReturnNone` by default. It
+    /// isn't saved with the `BField`, so it needs setting again after loading.
+    pub fn set_indeterminate_behavior(&mut self, behavior: IndeterminateBehavior) {
+        self.indeterminate_behavior = behavior;
+    }
+
+    /// Sets the cache levels marker positions are prefetched into by all the lookups
+    /// and inserts, see `PrefetchLocality`.
+    pub fn set_prefetch_locality(&mut self, locality: PrefetchLocality) {
+        for member in self.members.iter_mut() {
+            member.set_prefetch_locality(locality);
+        }
+    }
+
+    /// Sets how many keys ahead `insert_many` and `get_many` prefetch marker positions,
+    /// 4 by default. Slower memory (e.g. a file not in the page cache yet) benefits
+    /// from looking further ahead.
+    pub fn set_prefetch_distance(&mut self, distance: usize) {
+        for member in self.members.iter_mut() {
+            member.set_prefetch_distance(distance);
+        }
+    }
+
+    /// Sets how many markers of a key lookups read before prefetching the remaining
+    /// ones. A marker with too few bits set is enough to know a key is absent, so 1 or 2
+    /// saves most of the prefetches when looking up mostly absent keys, at the cost of
+    /// a few reads that don't overlap for present ones. 0 (the default) prefetches them
+    /// all up front, which is best when most keys are present.
+    pub fn set_probe_markers(&mut self, probe_markers: usize) {
+        for member in self.members.iter_mut() {
+            member.set_probe_markers(probe_markers);
+        }
+    }
+
+    /// Passes an access pattern hint for all the arrays on to the OS, e.g.
+    /// `AccessPattern::Random` after loading a `BField` that is only going to be queried.
+    pub fn advise(&self, pattern: AccessPattern) -> Result<(), io::Error> {
+        for member in &self.members {
+            member.advise(pattern)?;
+        }
+        Ok(())
+    }
+
+    /// Pages every array in ahead of time by reading through them once, trading a
+    /// one-off cost (roughly a sequential read of the files) for consistent latency of
+    /// the first lookups after `load`. This is separate from `load` so that batch jobs,
+    /// which don't care, can skip it.
+    pub fn preload(&self) -> Result<(), io::Error> {
+        for member in &self.members {
+            member.preload()?;
+        }
+        Ok(())
+    }
+
+    /// Get the info of each secondary array (`BFieldMember`) in the `BField`.
+    /// Returns `Vec<(size, n_hashes, marker_width, n_marker_bits)>`.
+    #[deprecated(note = "use `member_info`, which names the fields")]
+    pub fn info(&self) -> Vec<(usize, u8, u8, u8)> {
+        self.members.iter().map(|m| m.info()).collect()
+    }
+
+    /// Get the size and hashing parameters of each array (`BFieldMember`) in the `BField`,
+    /// starting with the primary one.
+    pub fn member_info(&self) -> Vec<MemberInfo> {
+        self.members.iter().map(|m| m.member_info()).collect()
+    }
+
+    /// Returns the number of arrays, i.e. the valid `pass`es of `insert` are `0..n_members()`.
+    pub fn n_members(&self) -> usize {
+        self.members.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combinatorial::rank;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn can_build_and_query_file_bfield() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let n_secondaries = 4;
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            n_secondaries,
+            false,
+            String::new(),
+        )
+        .expect("to build");
+
+        // Identity database
+        let max_value: u64 = 10_000;
+        for p in 0..n_secondaries {
+            for i in 0..max_value {
+                bfield.insert(&i.to_be_bytes().to_vec(), i, p as usize);
+            }
+        }
+
+        for i in 0..max_value {
+            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
+            assert_eq!(i, val);
+        }
+        drop(bfield);
+
+        // and we can load them
+        let bfield = BField::<String>::load(&tmp_dir.path().join("bfield.0.bfd"), true).unwrap();
+        for i in 0..max_value {
+            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
+            assert_eq!(i, val);
+        }
+    }
+
+    #[test]
+    fn cannot_insert_into_or_clear_read_only_bfield() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(0u64.to_be_bytes(), 0, 0);
+        drop(bfield);
+
+        let mut bfield = BField::<String>::load(tmp_dir.path().join("bfield.0.bfd"), true).unwrap();
+        assert_eq!(
+            bfield.try_insert(1u64.to_be_bytes(), 1, 0),
+            Err(InsertError::ReadOnly)
+        );
+        assert_eq!(
+            bfield.clear().unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(bfield.get(0u64.to_be_bytes()), Some(0));
+    }
+
+    #[test]
+    fn can_build_and_query_in_memory_bfield() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let n_secondaries = 4;
+        let mut bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            n_secondaries,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+
+        // Identity database
+        let max_value: u64 = 10_000;
+        for p in 0..n_secondaries {
+            for i in 0..max_value {
+                bfield.insert(&i.to_be_bytes().to_vec(), i, p as usize);
+            }
+        }
+
+        for i in 0..max_value {
+            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
+            assert_eq!(i, val);
+        }
+        bfield = bfield.persist_to_disk().unwrap();
+        for m in &bfield.members {
+            assert!(m.filename.exists());
+        }
+        for i in 0..max_value {
+            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
+            assert_eq!(i, val);
+        }
+    }
+
+    #[test]
+    fn can_clear_in_memory_bfield() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        bfield.clear().unwrap();
+        for i in 0..100u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), None);
+        }
+        // and it can be reused
+        bfield.insert(b"key", 5, 0);
+        assert_eq!(bfield.get(b"key"), Some(5));
+    }
+
+    #[test]
+    fn can_insert_many() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let n_secondaries = 4;
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            n_secondaries,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+
+        let max_value: u64 = 10_000;
+        let keys: Vec<[u8; 8]> = (0..max_value).map(|i| i.to_be_bytes()).collect();
+        let items: Vec<(&[u8], BFieldVal)> =
+            keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+        assert_eq!(bfield.insert_many(&items, 0).unwrap(), items.len());
+        for p in 1..n_secondaries {
+            // only the indeterminate keys make it to the secondaries
+            assert!(bfield.insert_many(&items, p as usize).unwrap() < items.len());
+        }
+
+        for i in 0..max_value {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        let saturation = bfield.saturation();
+        assert_eq!(saturation.len(), n_secondaries as usize);
+        assert!(saturation[0] > saturation[1]);
+        assert!(saturation.iter().all(|r| *r < 1.));
+
+        // and look them up in bulk, including some missing keys
+        let keys: Vec<[u8; 8]> = (0..2 * max_value).map(|i| i.to_be_bytes()).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+        let expected: Vec<Option<BFieldVal>> = keys.iter().map(|k| bfield.get(k)).collect();
+        assert_eq!(bfield.get_many(&keys), expected);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn can_par_build() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let build = || {
+            BField::create(
+                tmp_dir.path(),
+                "bfield",
+                1_000_000,
+                10,
+                39,
+                4,
+                0.1,
+                0.025,
+                4,
+                true,
+                String::new(),
+            )
+            .expect("to build")
+        };
+
+        let max_value: u64 = 10_000;
+        let keys: Vec<[u8; 8]> = (0..max_value).map(|i| i.to_be_bytes()).collect();
+        let items: Vec<(&[u8], BFieldVal)> =
+            keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+
+        let mut bfield = build();
+        bfield.par_build(&items).unwrap();
+        let sequential = build();
+        for p in 0..4 {
+            sequential.insert_many(&items, p).unwrap();
+        }
+
+        for i in 0..2 * max_value {
+            let key = i.to_be_bytes();
+            assert_eq!(bfield.get_checked(key), sequential.get_checked(key));
+        }
+        for i in 0..max_value {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn can_par_get_many() {
+        use rayon::prelude::*;
+
+        let bfield = BField::in_memory(1_000_000, 10, 39, 4, 0.1, 0.025, 4, String::new())
+            .expect("to build");
+        let keys: Vec<[u8; 8]> = (0..10_000u64).map(|i| i.to_be_bytes()).collect();
+        let items: Vec<(&[u8], BFieldVal)> =
+            keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+        for p in 0..4 {
+            bfield.insert_many(&items, p).unwrap();
+        }
+
+        // spans several chunks, the last one partial, with absent keys in between
+        let keys: Vec<[u8; 8]> = (0..3 * PAR_GET_CHUNK as u64 + 7)
+            .map(|i| (i * 3).to_be_bytes())
+            .collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+        let expected = bfield.get_many(&keys);
+        assert!(expected.contains(&None));
+        assert_eq!(bfield.par_get_many(&keys), expected);
+        let mapped: Vec<Option<BFieldVal>> = keys.par_iter().map(|k| bfield.get(k)).collect();
+        assert_eq!(mapped, expected);
+        assert!(bfield.par_get_many(&[]).is_empty());
+    }
+
+    #[test]
+    fn can_merge() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let build = |size, n_secondaries| {
+            BField::create(
+                tmp_dir.path(),
+                "bfield",
+                size,
+                10,
+                39,
+                4,
+                0.1,
+                0.025,
+                n_secondaries,
+                true,
+                String::new(),
+            )
+            .expect("to build")
+        };
+
+        let mut bfield = build(1_000_000, 2);
+        let other = build(1_000_000, 2);
+        for i in 0..1_000u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+            other.insert((i + 1_000).to_be_bytes(), i, 0);
+        }
+        bfield.merge(&other).unwrap();
+        for i in 0..1_000u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+            assert_eq!(bfield.get((i + 1_000).to_be_bytes()), Some(i));
+        }
+
+        assert_eq!(
+            bfield.merge(&build(1_000_000, 3)),
+            Err(MergeError::MemberCountMismatch {
+                expected: 2,
+                found: 3
+            })
+        );
+        assert_eq!(
+            bfield.merge(&build(100_000, 2)),
+            Err(MergeError::IncompatibleParams)
+        );
+    }
+
+    #[test]
+    fn can_use_any_bytes_as_key() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            10_000,
+            3,
+            16,
+            4,
+            0.1,
+            0.025,
+            1,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"literal", 1, 0);
+        bfield.insert(String::from("string"), 2, 0);
+        bfield.insert(vec![1u8, 2, 3], 3, 0);
+        bfield.force_insert("str", 4);
+
+        assert_eq!(bfield.get("literal"), Some(1));
+        assert_eq!(bfield.get(b"string"), Some(2));
+        assert_eq!(bfield.get([1u8, 2, 3]), Some(3));
+        assert_eq!(bfield.get_checked(String::from("str")), Ok(Some(4)));
+        assert!(bfield.contains_key(vec![1u8, 2, 3]));
+    }
+
+    #[test]
+    fn can_build_and_load_in_subdirectory() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let directory = tmp_dir.path().join("data").join("index");
+        std::fs::create_dir_all(&directory).unwrap();
+        let bfield = BField::create(
+            &directory,
+            "bfield",
+            10_000,
+            3,
+            16,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 42, 0);
+        drop(bfield);
+
+        assert!(directory.join("bfield.0.bfd").exists());
+        assert!(directory.join("bfield.1.bfd").exists());
+        assert!(!Path::new("bfield.0.bfd").exists());
+
+        let bfield = BField::<String>::load(directory.join("bfield.0.bfd"), true).unwrap();
+        assert_eq!(bfield.member_info().len(), 2);
+        assert_eq!(
+            bfield.secondary_params(),
+            Some(SecondaryParams {
+                secondary_scaledown: 0.1,
+                max_scaledown: 0.025,
+                n_secondaries: 2,
+            })
+        );
+        assert_eq!(bfield.get(b"test"), Some(42));
+    }
+
+    #[test]
+    fn can_force_insert_checked() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let create = |size: usize, n_hashes: u8, n_secondaries: u8| {
+            BField::create(
+                tmp_dir.path(),
+                "bfield",
+                size,
+                n_hashes,
+                64,
+                4,
+                1.,
+                1.,
+                n_secondaries,
+                true,
+                String::new(),
+            )
+            .expect("to build")
+        };
+
+        let bfield = create(1024, 3, 2);
+        assert_eq!(
+            bfield.force_insert_checked(b"test", 2),
+            Ok(ForceInsertOutcome::Inserted)
+        );
+        assert_eq!(
+            bfield.force_insert_checked(b"test", 2),
+            Ok(ForceInsertOutcome::AlreadyPresent)
+        );
+        // masked in the primary array, then inserted in the secondary one
+        assert_eq!(
+            bfield.force_insert_checked(b"test", 3),
+            Ok(ForceInsertOutcome::Masked { inserted: true })
+        );
+        assert_eq!(bfield.get(b"test"), Some(3));
+        assert!(bfield.force_insert_checked(b"test", 1 << 40).is_err());
+
+        let bfield = create(1024, 3, 1);
+        bfield.insert(b"test", 2, 0);
+        assert_eq!(
+            bfield.force_insert_checked(b"test", 3),
+            Ok(ForceInsertOutcome::Masked { inserted: false })
+        );
+        assert_eq!(
+            bfield.force_insert_checked(b"test", 4),
+            Ok(ForceInsertOutcome::Indeterminate)
+        );
+        assert_eq!(bfield.get_checked(b"test"), Err(Indeterminate));
+    }
+
+    #[test]
+    fn can_rebuild_from_keys() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create_with_seed(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            42,
+            String::from("extra"),
+        )
+        .expect("to build");
+        let keys: Vec<[u8; 8]> = (0..1_000u64).map(|i| i.to_be_bytes()).collect();
+        for (value, key) in keys.iter().enumerate() {
+            bfield.insert(key, value as BFieldVal, 0);
+        }
+
+        let path = tmp_dir.path().join("compact");
+        let rebuilt = bfield
+            .rebuild_from_keys(&path, 300_000, false, keys.iter().chain([&[0xff; 8]]))
+            .expect("to rebuild");
+        assert_eq!(rebuilt.build_params(), (3, 64, 4, vec![300_000, 30_000]));
+        assert_eq!(rebuilt.seed(), 42);
+        assert_eq!(rebuilt.params(), &Some("extra".to_string()));
+        for (value, key) in keys.iter().enumerate() {
+            assert_eq!(rebuilt.get(key), Some(value as BFieldVal));
+        }
+        // the key that wasn't in the original isn't inserted
+        assert_eq!(rebuilt.len(), 1_000);
+        assert!(tmp_dir.path().join("compact.0.bfd").exists());
+    }
+
+    #[test]
+    fn can_force_insert_and_mask_many() {
+        let bfield =
+            BField::in_memory(100_000, 3, 64, 4, 1., 1., 2, String::new()).expect("to build");
+        let keys: Vec<[u8; 8]> = (0..100u64).map(|i| i.to_be_bytes()).collect();
+        for (value, key) in keys.iter().enumerate() {
+            bfield.insert(key, value as BFieldVal, 0);
+        }
+
+        let mut to_mask: Vec<&[u8]> = keys[..10].iter().map(|k| &k[..]).collect();
+        to_mask.extend([&b"a"[..], b"b", b"c"]);
+        let stats = bfield.mask_many(&to_mask).unwrap();
+        assert_eq!(
+            (stats.masked, stats.absent, stats.indeterminate),
+            (10, 3, 0)
+        );
+        assert!(stats.bits_set > 0 && stats.bits_set <= 30);
+        for (value, key) in keys.iter().enumerate() {
+            let expected = if value < 10 {
+                None
+            } else {
+                Some(value as BFieldVal)
+            };
+            assert_eq!(bfield.get(key), expected);
+        }
+
+        let mut items: Vec<(&[u8], BFieldVal)> = Vec::new();
+        // masked above, so they go in the secondary array
+        items.extend(keys[..5].iter().map(|k| (&k[..], 7)));
+        items.extend(keys[10..15].iter().zip(10..).map(|(k, v)| (&k[..], v)));
+        items.extend(keys[15..20].iter().map(|k| (&k[..], 999)));
+        let stats = bfield.force_insert_many(&items).unwrap();
+        assert_eq!(
+            (
+                stats.inserted,
+                stats.already_present,
+                stats.masked,
+                stats.indeterminate
+            ),
+            (10, 5, 5, 0)
+        );
+        assert_eq!(bfield.get(keys[0]), Some(7));
+        assert_eq!(bfield.get(keys[15]), Some(999));
+
+        let len = bfield.len();
+        assert!(bfield
+            .force_insert_many(&[(b"new", 1), (b"too big", 1 << 40)])
+            .is_err());
+        assert_eq!(bfield.get(b"new"), None);
+        assert_eq!(bfield.len(), len);
+    }
+
+    #[test]
+    fn can_save_and_open_single_file() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_001,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            3,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for pass in 0..3 {
+            for i in 0..1_000u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        let path = tmp_dir.path().join("bfield.bfs");
+        bfield.save_single(&path).unwrap();
+
+        let single = BField::<String>::open_single(&path, true).unwrap();
+        assert_eq!(single.member_info(), bfield.member_info());
+        assert_eq!(single.params(), &Some("extra".to_string()));
+        assert_eq!(single.secondary_params(), bfield.secondary_params());
+        for i in 0..1_000u64 {
+            assert_eq!(single.get(i.to_be_bytes()), bfield.get(i.to_be_bytes()));
+        }
+        assert_eq!(single.try_insert(b"test", 1, 0), Err(InsertError::ReadOnly));
+
+        // truncated files are errors, not panics
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &bytes[..bytes.len() / 2]).unwrap();
+        let err = BField::<String>::open_single(&path, true).err().unwrap();
+        assert!(matches!(BFieldError::from(err), BFieldError::Corrupt(_)));
+        std::fs::write(&path, b"garbage").unwrap();
+        assert!(BField::<String>::open_single(&path, true).is_err());
+    }
+
+    #[test]
+    fn can_round_trip_through_bytes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for pass in 0..2 {
+            for i in 0..1_000u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        let bytes = bfield.to_bytes().unwrap();
+        let path = tmp_dir.path().join("copy.bfs");
+        let copy = BField::<String>::from_bytes(&path, &bytes, false).unwrap();
+        assert_eq!(copy.member_info(), bfield.member_info());
+        assert_eq!(copy.params(), &Some("extra".to_string()));
+        assert_eq!(copy.len(), bfield.len());
+        for i in 0..2_000u64 {
+            assert_eq!(copy.lookup(i.to_be_bytes()), bfield.lookup(i.to_be_bytes()));
+        }
+
+        // the copy is written next to `path` when persisted
+        copy.persist_to_disk().unwrap();
+        assert!(tmp_dir.path().join("copy.1.bfd").exists());
+        assert!(BField::<String>::from_bytes(&path, &bytes[..bytes.len() - 1], true).is_err());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn can_save_and_open_compressed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for pass in 0..2 {
+            for i in 0..1_000u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        let path = tmp_dir.path().join("bfield.bfz");
+        bfield.save_compressed(&path, 0).unwrap();
+        // a sparse B-field compresses well
+        let single_len = bfield.to_bytes().unwrap().len() as u64;
+        assert!(std::fs::metadata(&path).unwrap().len() < single_len / 4);
+
+        let compressed = BField::<String>::open_compressed(&path, true).unwrap();
+        assert_eq!(compressed.member_info(), bfield.member_info());
+        assert_eq!(compressed.params(), &Some("extra".to_string()));
+        for i in 0..2_000u64 {
+            assert_eq!(
+                compressed.lookup(i.to_be_bytes()),
+                bfield.lookup(i.to_be_bytes())
+            );
+        }
+        assert!(
+            BField::<String>::open_compressed(tmp_dir.path().join("bfield.0.bfd"), true).is_err()
+        );
+    }
+
+    #[test]
+    fn can_use_custom_member_naming() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let naming = |n: usize| tmp_dir.path().join(format!("mmap.secondary.{:03}", n));
+        let bfield = BField::create_with_naming(
+            naming,
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            3,
+            false,
+            0,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 42, 0);
+        drop(bfield);
+
+        assert!(tmp_dir.path().join("mmap.secondary.000").exists());
+        assert!(tmp_dir.path().join("mmap.secondary.002").exists());
+        assert!(!tmp_dir.path().join("mmap.secondary.003").exists());
+
+        let bfield = BField::<String>::load_with_naming(naming, true).unwrap();
+        assert_eq!(bfield.member_info().len(), 3);
+        assert_eq!(bfield.get(b"test"), Some(42));
+    }
+
+    #[test]
+    fn can_use_custom_hasher() {
+        // FNV-1a with a splitmix64 finalizer, good enough to spread the keys of a test
+        #[derive(Clone)]
+        struct Fnv;
+        impl BFieldHasher for Fnv {
+            fn name(&self) -> &str {
+                "fnv"
+            }
+            fn hash128(&self, key: &[u8], seed: u64) -> (u64, u64) {
+                let hash = |basis: u64| {
+                    let mut h = key.iter().fold(basis, |h, &b| {
+                        (h ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+                    });
+                    h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                    h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                    h ^ (h >> 31)
+                };
+                (hash(0xcbf2_9ce4_8422_2325 ^ seed), hash(!seed))
+            }
+        }
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let naming = |n: usize| tmp_dir.path().join(format!("bfield.{n}.bfd"));
+        let bfield = BField::create_with_hasher(
+            naming,
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            0,
+            Fnv,
+            String::new(),
+        )
+        .expect("to build");
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        drop(bfield);
+
+        let bfield = BField::<String, Fnv>::load_with_hasher(naming, true, Fnv).unwrap();
+        for i in 0..100u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        let err = BField::<String>::load(naming(0), true).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn can_report_memory_bytes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_001,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            4,
+            false,
+            String::from("extra"),
+        )
+        .expect("to build");
+        let file_lens = |bfield: &BField<String>| -> u64 {
+            (0..bfield.n_members())
+                .map(|i| {
+                    let path = tmp_dir.path().join(format!("bfield.{}.bfd", i));
+                    fs::metadata(path).unwrap().len()
+                })
+                .sum()
+        };
+        assert_eq!(bfield.memory_bytes() as u64, file_lens(&bfield));
+
+        // persisting adds a checksum to the headers
+        bfield.insert(b"test", 1, 0);
+        let bfield = bfield.persist_to_disk().unwrap();
+        assert_eq!(bfield.memory_bytes() as u64, file_lens(&bfield));
+    }
+
+    #[test]
+    fn can_create_auto() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let n_keys = 20_000u64;
+        let (bfield, layout) = BField::create_auto(
+            tmp_dir.path(),
+            "bfield",
+            n_keys as usize,
+            3,
+            32,
+            4,
+            0.1,
+            0.025,
+            true,
+            1e-4,
+            String::from("extra"),
+        )
+        .expect("to build");
+        assert!(layout.sizes.len() > 1);
+        let sizes: Vec<usize> = bfield.member_info().iter().map(|i| i.size).collect();
+        assert_eq!(sizes, layout.sizes);
+
+        for pass in 0..bfield.n_members() {
+            for i in 0..n_keys {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        let n_missing = (0..n_keys)
+            .filter(|i| bfield.get(i.to_be_bytes()) != Some(*i))
+            .count();
+        assert!(n_missing < 10, "{} keys missing", n_missing);
+
+        let res = BField::create_auto(
+            tmp_dir.path(),
+            "bfield",
+            1_000,
+            3,
+            32,
+            4,
+            0.1,
+            0.025,
+            true,
+            0.,
+            String::new(),
+        );
+        assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        // rather than looping forever trying to size the arrays
+        let res = BField::create_auto(
+            tmp_dir.path(),
+            "bfield",
+            1_000,
+            0,
+            32,
+            4,
+            0.1,
+            0.025,
+            true,
+            0.01,
+            String::new(),
+        );
+        assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        // the scaledowns are checked like in `create`, which allows 1
+        let (bfield, _) = BField::create_auto(
+            tmp_dir.path(),
+            "flat",
+            1_000,
+            3,
+            32,
+            4,
+            1.,
+            1.,
+            true,
+            0.01,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 1, 0);
+        assert_eq!(bfield.get(b"test"), Some(1));
+        for (secondary_scaledown, max_scaledown) in &[(1.5, 0.025), (0.1, 0.)] {
+            let res = BField::create_auto(
+                tmp_dir.path(),
+                "bfield",
+                1_000,
+                3,
+                32,
+                4,
+                *secondary_scaledown,
+                *max_scaledown,
+                true,
+                0.01,
+                String::new(),
+            );
+            assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn can_replace() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        assert_eq!(bfield.replace(b"test", 10, 0), Ok(BFieldLookup::None));
+        assert_eq!(bfield.replace(b"test", 10, 0), Ok(BFieldLookup::Some(10)));
+        // the primary array already has it so the secondary one isn't touched
+        assert_eq!(bfield.replace(b"test", 10, 1), Ok(BFieldLookup::Some(10)));
+        assert_eq!(bfield.replace(b"test", 11, 0), Ok(BFieldLookup::Some(10)));
+        // now indeterminate in the primary array and not in the secondary one yet
+        assert_eq!(bfield.lookup(b"test"), BFieldLookup::None);
+        assert_eq!(bfield.replace(b"test", 11, 1), Ok(BFieldLookup::None));
+        assert_eq!(bfield.get(b"test"), Some(11));
+        assert_eq!(bfield.len(), 3);
+        assert_eq!(
+            bfield.replace(b"test", 11, 2),
+            Err(InsertError::InvalidPass {
+                pass: 2,
+                n_members: 2
+            })
+        );
+    }
+
+    #[test]
+    fn can_use_prehashed_keys() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create_with_seed(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            42,
+            String::from("extra"),
+        )
+        .expect("to build");
+        assert_eq!(bfield.seed(), 42);
+        let hash = |key: &[u8]| murmurhash3::murmurhash3_x64_128(key, bfield.seed());
+
+        assert_eq!(bfield.try_insert_prehashed(hash(b"a"), 10, 0), Ok(true));
+        bfield.insert(b"b", 20, 0);
+        assert_eq!(bfield.get(b"a"), Some(10));
+        assert_eq!(bfield.lookup_prehashed(hash(b"b")), BFieldLookup::Some(20));
+        assert_eq!(bfield.lookup_prehashed(hash(b"c")), BFieldLookup::None);
+        assert_eq!(bfield.hash_key(b"a"), hash(b"a"));
+        assert_eq!(bfield.hash_key(String::from("a")), hash(b"a"));
+        assert_ne!(
+            bfield.hash_key(b"a"),
+            murmurhash3::murmurhash3_x64_128(b"a", 0)
+        );
+        // already resolved by the primary array
+        assert_eq!(bfield.try_insert_prehashed(hash(b"a"), 10, 1), Ok(false));
+        assert_eq!(bfield.len(), 2);
+    }
+
+    #[test]
+    fn can_get_multiple_values() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        assert!(bfield.get_multi(b"kmer").is_empty());
+        bfield.insert(b"kmer", 5, 0);
+        assert_eq!(bfield.get_multi(b"kmer"), vec![5]);
+        bfield.insert(b"kmer", 1_234, 0);
+        assert_eq!(bfield.get(b"kmer"), None);
+        let values = bfield.get_multi(b"kmer");
+        assert!(values.contains(&5) && values.contains(&1_234));
+        assert!(values.len() <= 70);
+    }
+
+    #[test]
+    fn can_get_with_member() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            32,
+            4,
+            0.1,
+            0.025,
+            3,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for pass in 0..3 {
+            for i in 0..1_500u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        let mut per_member = [0; 3];
+        for i in 0..1_500u64 {
+            let key = i.to_be_bytes();
+            let found = bfield.get_with_member(key);
+            assert_eq!(found.map(|(value, _)| value), bfield.get(key));
+            if let Some((_, ix)) = found {
+                per_member[ix] += 1;
+            }
+        }
+        assert!(per_member[0] > 1_000);
+        assert!(per_member[1] > 0);
+        assert_eq!(bfield.get_with_member(b"not there"), None);
+    }
+
+    #[test]
+    fn can_build_with_paths() {
+        let disks = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let paths = vec![
+            disks[0].path().join("primary.bfd"),
+            disks[1].path().join("secondary-1.bfd"),
+            disks[0].path().join("secondary-2.bfd"),
+        ];
+        assert_eq!(
+            BField::create_with_paths(
+                &paths[..2],
+                100_000,
+                10,
+                39,
+                4,
+                0.1,
+                0.025,
+                3,
+                false,
+                0,
+                String::new(),
+            )
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let bfield = BField::create_with_paths(
+            &paths,
+            100_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            3,
+            false,
+            0,
+            String::new(),
+        )
+        .expect("to build");
+        for i in 0..1_000u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        drop(bfield.persist_to_disk().unwrap());
+        assert!(paths.iter().all(|path| path.exists()));
+
+        let bfield = BField::<String>::load_with_paths(&paths, true).unwrap();
+        assert_eq!(bfield.n_members(), 3);
+        for i in 0..1_000u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        assert_eq!(
+            BField::<String>::load_with_paths(&paths[..2], true)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidInput
+        );
+        let mut missing = paths.clone();
+        missing[1] = disks[1].path().join("nope.bfd");
+        assert_eq!(
+            BField::<String>::load_with_paths(&missing, true)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        // the arrays need to be in the order they were created in
+        let mut swapped = paths.clone();
+        swapped.swap(1, 2);
+        assert_eq!(
+            BField::<String>::load_with_paths(&swapped, true)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+        swapped.swap(0, 1);
+        assert_eq!(
+            BField::<String>::load_with_paths(&swapped, true)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn can_get_detailed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            32,
+            4,
+            0.1,
+            0.025,
+            3,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        for pass in 0..3 {
+            for i in 0..1_500u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        let mut borderline = 0;
+        for i in 0..1_500u64 {
+            let key = i.to_be_bytes();
+            let detailed = bfield.get_detailed(key);
+            assert_eq!(detailed.map(|(value, _)| value), bfield.get(key));
+            match (detailed, bfield.get_with_member(key)) {
+                (Some((_, popcount)), Some((_, 0))) => assert_eq!(popcount, 4),
+                (Some((_, popcount)), Some(_)) => {
+                    assert!(popcount > 4);
+                    borderline += 1;
+                }
+                _ => {}
+            }
+        }
+        assert!(borderline > 0);
+        assert_eq!(bfield.get_detailed(b"not there"), None);
+    }
+
+    #[test]
+    fn can_count_inserts() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        assert!(bfield.is_empty());
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+            bfield.insert(i.to_be_bytes(), i, 1);
+        }
+        let items: Vec<([u8; 8], BFieldVal)> =
+            (100..150u64).map(|i| (i.to_be_bytes(), i)).collect();
+        let items: Vec<(&[u8], BFieldVal)> = items.iter().map(|(k, v)| (&k[..], *v)).collect();
+        bfield.insert_many(&items, 0).unwrap();
+        bfield.force_insert(b"new", 1);
+        bfield.force_insert(b"new", 1);
+        assert_eq!(bfield.len(), 151);
+        // re-inserting keys with the value they already have doesn't count them twice
+        assert_eq!(bfield.insert_if_absent(b"new", 1, 0), Ok(false));
+        assert_eq!(bfield.insert_if_absent(5u64.to_be_bytes(), 5, 0), Ok(false));
+        assert_eq!(bfield.insert_if_absent(b"newer", 2, 0), Ok(true));
+        assert_eq!(bfield.len(), 152);
+
+        let bfield = bfield.persist_to_disk().unwrap();
+        drop(bfield);
+        let bfield = BField::<String>::load(tmp_dir.path().join("bfield.0.bfd"), true).unwrap();
+        assert_eq!(bfield.len(), 152);
+        assert!(!bfield.is_empty());
+    }
+
+    #[test]
+    fn can_count_inserts_on_disk() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield");
+        let bfield: BField<String> = BFieldBuilder::new(100_000)
+            .n_secondaries(2)
+            .build(&path)
+            .unwrap();
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        // the count is saved without `persist_to_disk`
+        drop(bfield);
+        let bfield = BField::<String>::load(path.with_extension("0.bfd"), false).unwrap();
+        assert_eq!(bfield.len(), 100);
+
+        bfield.insert(b"new", 1, 0);
+        bfield.flush().unwrap();
+        let reopened = BField::<String>::load(path.with_extension("0.bfd"), true).unwrap();
+        assert_eq!(reopened.len(), 101);
+    }
+
+    #[test]
+    #[should_panic(expected = "indeterminate in every member")]
+    fn can_panic_on_indeterminate() {
+        let mut bfield =
+            BField::in_memory(100_000, 3, 64, 4, 0.1, 0.025, 1, String::new()).expect("to build");
+        // two different values make the key indeterminate in the only member
+        bfield.insert(b"test", 1, 0);
+        bfield.insert(b"test", 2, 0);
+        assert_eq!(bfield.get(b"test"), None);
+        assert_eq!(bfield.get_checked(b"test"), Err(Indeterminate));
+        assert_eq!(bfield.get_many(&[b"test", b"other"]), vec![None, None]);
+
+        bfield.set_indeterminate_behavior(IndeterminateBehavior::Panic);
+        assert_eq!(bfield.get(b"other"), None);
+        bfield.get(b"test");
+    }
+
+    #[test]
+    #[should_panic(expected = "indeterminate in every member")]
+    fn contains_key_follows_indeterminate_behavior() {
+        let mut bfield =
+            BField::in_memory(100_000, 3, 64, 4, 0.1, 0.025, 1, String::new()).expect("to build");
+        bfield.insert(b"test", 1, 0);
+        bfield.insert(b"test", 2, 0);
+        assert!(!bfield.contains_key(b"test"));
+
+        bfield.set_indeterminate_behavior(IndeterminateBehavior::Panic);
+        bfield.contains_key(b"test");
+    }
+
+    #[test]
+    fn can_return_err_on_indeterminate() {
+        let mut bfield =
+            BField::in_memory(100_000, 3, 64, 4, 0.1, 0.025, 1, String::new()).expect("to build");
+        bfield.insert(b"test", 1, 0);
+        bfield.insert(b"test", 2, 0);
+        bfield.insert(b"known", 3, 0);
+        assert_eq!(bfield.try_get(b"test"), Ok(None));
+
+        bfield.set_indeterminate_behavior(IndeterminateBehavior::ReturnErr);
+        assert_eq!(bfield.try_get(b"test"), Err(Indeterminate));
+        assert_eq!(bfield.try_get(b"known"), Ok(Some(3)));
+        assert_eq!(bfield.try_get(b"other"), Ok(None));
+        assert_eq!(bfield.get(b"test"), None);
+        assert!(!bfield.contains_key(b"test"));
+    }
+
+    #[test]
+    fn can_create_in_memory() {
+        let bfield = BField::in_memory(100_000, 3, 64, 4, 0.1, 0.025, 2, String::from("extra"))
+            .expect("to build");
+        assert_eq!(bfield.params(), &Some("extra".to_string()));
+        assert_eq!(bfield.build_params().3, vec![100_000, 10_000]);
+        assert!(bfield.check_encoding().is_ok());
+        bfield.insert(b"test", 10, 0);
+        assert_eq!(bfield.get(b"test"), Some(10));
+        assert_eq!(bfield.get_in_range(b"test", 10, 19), Some(true));
+        assert_eq!(bfield.get_in_range(b"test", 0, 9), Some(false));
+        assert_eq!(bfield.get_in_range(b"other", 0, 100), None);
+
+        let bytes = bfield.to_bytes().expect("to serialize");
+        assert!(bfield.persist_to_disk().is_err());
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield: BField<String> =
+            BField::from_bytes(tmp_dir.path().join("bfield"), &bytes, true).unwrap();
+        assert_eq!(bfield.params(), &Some("extra".to_string()));
+        assert_eq!(bfield.get(b"test"), Some(10));
+    }
+
+    #[test]
+    fn can_simulate_build() {
+        let full =
+            BField::in_memory(1_000_000, 3, 64, 4, 0.1, 0.025, 3, String::new()).expect("to build");
+        let keys: Vec<[u8; 8]> = (0..20_000u64).map(|i| i.to_be_bytes()).collect();
+        let items: Vec<(&[u8], BFieldVal)> = keys
+            .iter()
+            .zip((0..1_000).cycle())
+            .map(|(k, v)| (&k[..], v))
+            .collect();
+        let mut inserted = Vec::new();
+        for pass in 0..full.n_members() {
+            inserted.push(full.insert_many(&items, pass).unwrap());
+        }
+
+        let report =
+            BField::<String>::simulate_build(&items[..2_000], 20_000, &full.config()).unwrap();
+        // the sample is a tenth of the keys, built into arrays a tenth of the size
+        assert_eq!(report.sizes, vec![100_000, 10_000, 2_500]);
+        assert_eq!(report.inserted[0], 2_000);
+        for (simulated, actual) in report.fill_ratios.iter().zip(full.saturation()) {
+            assert!((simulated - actual).abs() < 0.03);
+        }
+        for (simulated, actual) in report.inserted.iter().zip(&inserted) {
+            let (simulated, actual) = (*simulated as f64 / 2e3, *actual as f64 / 2e4);
+            assert!((simulated - actual).abs() < 0.03);
+        }
+        let n_indeterminate = items
+            .iter()
+            .filter(|(key, _)| full.get_checked(key).is_err())
+            .count();
+        assert!((report.indeterminate_rate - n_indeterminate as f64 / 2e4).abs() < 0.03);
+
+        for (sample, n_keys) in [(&items[..0], 20_000), (&items[..2_000], 1_000)] {
+            let err = BField::<String>::simulate_build(sample, n_keys, &full.config()).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        // the smallest array would be narrower than a marker
+        let err =
+            BField::<String>::simulate_build(&items[..10], 20_000, &full.config()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn can_flush() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield");
+        let bfield: BField<String> = BFieldBuilder::new(100_000)
+            .n_secondaries(2)
+            .build(&path)
+            .unwrap();
+        for pass in 0..2 {
+            bfield.insert(b"test", 7, pass);
+        }
+        bfield.flush().unwrap();
+
+        let reopened: BField<String> = BField::load(path.with_extension("0.bfd"), true).unwrap();
+        assert_eq!(reopened.get(b"test"), Some(7));
+        assert!(reopened.flush().is_ok());
+    }
+
+    #[test]
+    fn can_grow() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield");
+        let mut bfield: BField<String> = BFieldBuilder::new(100_000)
+            .n_secondaries(2)
+            .build(&path)
+            .unwrap();
+        let keys: Vec<[u8; 8]> = (0..1_000u64).map(|i| i.to_be_bytes()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            bfield.insert(key, i as BFieldVal, 0);
+        }
+        let indeterminate = |bfield: &BField<String>| {
+            keys.iter()
+                .filter(|key| bfield.get_raw_markers(key)[0].1 > 4)
+                .count()
+        };
+        let before = indeterminate(&bfield);
+        assert!(before > 0);
+
+        bfield.grow(0, 1_000_000, &keys).unwrap();
+        assert_eq!(bfield.build_params().3, vec![1_000_000, 10_000]);
+        // the keys indeterminate in the primary array are still waiting for the next pass
+        assert!(indeterminate(&bfield) >= before);
+        for (i, key) in keys.iter().enumerate() {
+            bfield.insert(key, i as BFieldVal, 1);
+        }
+        drop(bfield);
+
+        let bfield: BField<String> = BField::load(path.with_extension("0.bfd"), false).unwrap();
+        assert_eq!(bfield.build_params().3, vec![1_000_000, 10_000]);
+        let found = keys
+            .iter()
+            .enumerate()
+            .filter(|(i, key)| bfield.get(key) == Some(*i as BFieldVal))
+            .count();
+        assert!(found > 990);
+
+        let mut bfield = bfield;
+        let err = bfield.grow(2, 2_000_000, &keys).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn can_tell_zero_from_absent() {
+        let bfield =
+            BField::in_memory(100_000, 3, 64, 4, 0.1, 0.025, 2, String::new()).expect("to build");
+        let one = NonZeroBFieldVal::new(1).unwrap();
+        for pass in 0..2 {
+            bfield.insert(b"zero", 0, pass);
+            bfield.insert_nonzero(b"one", one, pass);
+        }
+        // the marker of 0 has `n_marker_bits` bits set like any other value
+        assert_eq!(bfield.get_raw_markers(b"zero")[0], (rank(0, 4), 4));
+        assert_eq!(bfield.get(b"zero"), Some(0));
+        assert_eq!(bfield.get(b"absent"), None);
+        assert_eq!(bfield.get_checked(b"zero"), Ok(Some(0)));
+        assert!(bfield.contains_key(b"zero"));
+        assert!(!bfield.contains_key(b"absent"));
+
+        assert_eq!(bfield.get_nonzero(b"zero"), None);
+        assert_eq!(bfield.get_nonzero(b"absent"), None);
+        assert_eq!(bfield.get_nonzero(b"one"), Some(one));
+        assert_eq!(bfield.get(b"one"), Some(1));
+    }
+
+    #[test]
+    fn can_create_from_config() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield: BField<String> = BFieldBuilder::with_other_params(100_000, "extra".into())
+            .n_secondaries(3)
+            .seed(42)
+            .position_scheme(PositionScheme::EnhancedDoubleHashing)
+            .in_memory(true)
+            .build(tmp_dir.path().join("original"))
+            .unwrap();
+        let config = bfield.config();
+        assert_eq!(config.sizes, bfield.build_params().3);
+        assert_eq!(config.seed, 42);
+        assert_eq!(config.hasher, "murmur3");
+        assert_eq!(config.secondaries, bfield.secondary_params());
+
+        let json = serde_json::to_string_pretty(&config).unwrap();
+        assert!(json.contains("\"position_scheme\": \"EnhancedDoubleHashing\""));
+        let config: BFieldConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(config, bfield.config());
+
+        let path = tmp_dir.path().join("rebuilt");
+        let rebuilt: BField<String> =
+            BField::create_from_config(&path, &config, "other".into()).unwrap();
+        assert_eq!(rebuilt.config(), config);
+        assert_eq!(rebuilt.params(), &Some("other".to_string()));
+        for pass in 0..rebuilt.n_members() {
+            rebuilt.insert(b"test", 3, pass);
+        }
+        drop(rebuilt);
+        let mut rebuilt: BField<String> =
+            BField::load(path.with_extension("0.bfd"), false).unwrap();
+        assert_eq!(rebuilt.config(), config);
+        assert_eq!(rebuilt.get(b"test"), Some(3));
+        assert_eq!(rebuilt.merge(&bfield), Ok(()));
+
+        let mut other_hasher = config.clone();
+        other_hasher.hasher = "xxh3".into();
+        let mut no_arrays = config;
+        no_arrays.sizes.clear();
+        for config in [other_hasher, no_arrays] {
+            let err =
+                BField::create_from_config(tmp_dir.path().join("bad"), &config, String::new())
+                    .err()
+                    .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn can_build_from_iterators() {
+        let mut bfield = BField::from_iter_in_memory(
+            100_000,
+            String::from("extra"),
+            (0..1_000u64).map(|i| (i.to_be_bytes(), i)),
+        )
+        .expect("to build");
+        for i in 0..1_000u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        assert_eq!(bfield.params(), &Some("extra".to_string()));
+
+        bfield.extend(vec![(b"a".to_vec(), 1), (b"b".to_vec(), 2)]);
+        assert_eq!(bfield.get(b"a"), Some(1));
+        assert_eq!(bfield.get(b"b"), Some(2));
+        assert_eq!(bfield.len(), 1_002);
+    }
+
+    #[test]
+    fn can_clone() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        let clone = bfield.try_clone().unwrap();
+        assert_eq!(clone.member_info(), bfield.member_info());
+        assert_eq!(clone.params(), bfield.params());
+        assert_eq!(clone.len(), 100);
+
+        clone.insert(b"only in the clone", 42, 0);
+        assert_eq!(clone.get(b"only in the clone"), Some(42));
+        assert_eq!(bfield.get(b"only in the clone"), None);
+        assert_eq!(bfield.len(), 100);
+        for i in 0..100u64 {
+            assert_eq!(clone.get(i.to_be_bytes()), Some(i));
+        }
+    }
+
+    #[test]
+    fn can_load_into_memory() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        drop(bfield);
+
+        let path = tmp_dir.path().join("bfield.0.bfd");
+        let bfield = BField::<String>::load_into_memory(&path).unwrap();
+        // the files can go away once loaded
+        std::fs::remove_dir_all(tmp_dir.path()).unwrap();
+        for i in 0..100u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        assert_eq!(bfield.params(), &Some("extra".to_string()));
     }
 
     #[test]
-    fn can_build_and_query_in_memory_bfield() {
+    fn can_push_members() {
         let tmp_dir = tempfile::tempdir().unwrap();
-        let n_secondaries = 4;
         let mut bfield = BField::create(
             tmp_dir.path(),
             "bfield",
-            1_000_000,
-            10,
-            39,
+            100_000,
+            3,
+            64,
             4,
             0.1,
             0.025,
-            n_secondaries,
+            2,
+            false,
+            String::from("extra"),
+        )
+        .expect("to build");
+        let info = MemberInfo {
+            size: 10_000,
+            n_hashes: 3,
+            marker_width: 64,
+            n_marker_bits: 4,
+        };
+        let path = tmp_dir.path().join("bfield.2.bfd");
+        let err = bfield
+            .push_member(
+                &path,
+                MemberInfo {
+                    marker_width: 32,
+                    ..info
+                },
+            )
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+
+        assert_eq!(bfield.n_members(), 2);
+        assert_eq!(
+            bfield.try_insert(b"test", 42, 2),
+            Err(InsertError::InvalidPass {
+                pass: 2,
+                n_members: 2
+            })
+        );
+        assert_eq!(
+            bfield.insert_many(&[(b"test", 42)], 2),
+            Err(InsertError::InvalidPass {
+                pass: 2,
+                n_members: 2
+            })
+        );
+        bfield.push_member(&path, info).unwrap();
+        assert_eq!(bfield.n_members(), 3);
+        assert_eq!(bfield.member_info()[2], info);
+        assert_eq!(bfield.secondary_params().unwrap().n_secondaries, 3);
+        // absent from the earlier arrays so not inserted, but the pass is valid
+        assert!(!bfield.insert(b"test", 42, 2));
+        drop(bfield);
+
+        let bfield = BField::<String>::load(tmp_dir.path().join("bfield.0.bfd"), true).unwrap();
+        assert_eq!(bfield.member_info().len(), 3);
+        assert_eq!(bfield.member_info()[2], info);
+    }
+
+    #[test]
+    fn can_load_pushed_member_with_paths() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let paths: Vec<PathBuf> = (0..3)
+            .map(|i| tmp_dir.path().join(format!("bfield.{}.bfd", i)))
+            .collect();
+        let mut bfield = BField::create_with_paths(
+            &paths[..2],
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            0,
+            String::new(),
+        )
+        .expect("to build");
+        let size = member_sizes(100_000, 0.1, 0.025, 3)[2];
+        bfield
+            .push_member(
+                &paths[2],
+                MemberInfo {
+                    size,
+                    n_hashes: 3,
+                    marker_width: 64,
+                    n_marker_bits: 4,
+                },
+            )
+            .unwrap();
+        // make the key indeterminate in the first two arrays so it goes in the new one
+        for pass in 0..2 {
+            bfield.insert(b"test", 1, pass);
+            bfield.insert(b"test", 2, pass);
+        }
+        assert!(bfield.insert(b"test", 3, 2));
+        drop(bfield);
+
+        let bfield = BField::<String>::load_with_paths(&paths, true).unwrap();
+        assert_eq!(bfield.secondary_params().unwrap().n_secondaries, 3);
+        assert_eq!(bfield.get(b"test"), Some(3));
+        assert!(BField::<String>::load_with_paths(&paths[..2], true).is_err());
+    }
+
+    #[test]
+    fn can_build_from_reader() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let input_path = tmp_dir.path().join("input.tsv");
+        let mut input = BufWriter::new(File::create(&input_path).unwrap());
+        for i in 0..5_000u64 {
+            writeln!(input, "key{i}\t{}", i % 100).unwrap();
+        }
+        drop(input);
+
+        let path = tmp_dir.path().join("bfield");
+        // narrow markers so that some keys end up in the secondary arrays
+        let builder = BFieldBuilder::<String>::new(1_000_000)
+            .marker_width(16)
+            .n_secondaries(3);
+        let bfield = BField::build_from_reader(
+            &path,
+            &builder,
+            BufReader::new(File::open(&input_path).unwrap()),
+            |line| {
+                let (key, value) = line.split_once('\t').unwrap();
+                let value = value
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                Ok((key.as_bytes().to_vec(), value))
+            },
+        )
+        .expect("to build");
+
+        let mut per_member = [0; 3];
+        for i in 0..5_000u64 {
+            let (value, member) = bfield.get_with_member(format!("key{i}")).unwrap();
+            assert_eq!(value, i % 100);
+            per_member[member] += 1;
+        }
+        assert!(per_member[1] > 0);
+        assert_eq!(bfield.len(), 5_000);
+        assert!(!tmp_dir.path().join("bfield.spill.1").exists());
+        assert!(!tmp_dir.path().join("bfield.spill.2").exists());
+    }
+
+    #[test]
+    fn build_from_reader_removes_spill_files_on_error() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let input_path = tmp_dir.path().join("input.tsv");
+        let mut input = BufWriter::new(File::create(&input_path).unwrap());
+        for i in 0..5_000u64 {
+            writeln!(input, "key{i}\t{}", i % 100).unwrap();
+        }
+        drop(input);
+
+        let path = tmp_dir.path().join("bfield");
+        let builder = BFieldBuilder::<String>::new(1_000_000)
+            .marker_width(16)
+            .n_secondaries(3);
+        // fail halfway through reading the input a second time, once the first spill
+        // file is being written
+        let mut n_parsed = 0;
+        let err = BField::build_from_reader(
+            &path,
+            &builder,
+            BufReader::new(File::open(&input_path).unwrap()),
+            |line| {
+                n_parsed += 1;
+                if n_parsed == 7_500 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "bad line"));
+                }
+                let (key, value) = line.split_once('\t').unwrap();
+                Ok((key.as_bytes().to_vec(), value.parse().unwrap()))
+            },
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(n_parsed, 7_500);
+
+        let spill_files: Vec<_> = fs::read_dir(tmp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.contains(".spill."))
+            .collect();
+        assert!(spill_files.is_empty(), "{:?} left behind", spill_files);
+    }
+
+    #[test]
+    fn can_use_segmented_keys() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
             true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        assert!(bfield.insert_segments(&[b"pre", b"", b"fix"], 7, 0));
+        assert_eq!(bfield.get(b"prefix"), Some(7));
+        assert_eq!(bfield.get_segments(&[b"pref", b"ix"]), Some(7));
+        assert_eq!(bfield.get_segments(&[b"prefix", b"es"]), None);
+
+        // too long for the stack buffer
+        let long = vec![b'a'; SEGMENTS_STACK_LEN];
+        assert!(bfield.insert_segments(&[&long, b"b"], 8, 0));
+        assert_eq!(bfield.get([&long[..], b"b"].concat()), Some(8));
+        assert_eq!(
+            bfield.get_segments(&[&long[..10], &long[10..], b"b"]),
+            Some(8)
+        );
+    }
+
+    #[test]
+    fn can_tune_prefetching() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        let keys: Vec<[u8; 8]> = (0..200u64).map(|i| i.to_be_bytes()).collect();
+        let keys: Vec<&[u8]> = keys.iter().map(|k| &k[..]).collect();
+        let items: Vec<(&[u8], BFieldVal)> = keys.iter().zip(0..).map(|(k, v)| (*k, v)).collect();
+        bfield.set_prefetch_distance(0);
+        bfield.insert_many(&items[..100], 0).unwrap();
+        let expected = bfield.get_many(&keys);
+        for locality in [
+            PrefetchLocality::NonTemporal,
+            PrefetchLocality::Low,
+            PrefetchLocality::Medium,
+            PrefetchLocality::High,
+        ] {
+            for distance in [0, 1, 16, 1_000] {
+                bfield.set_prefetch_locality(locality);
+                bfield.set_prefetch_distance(distance);
+                assert_eq!(bfield.get_many(&keys), expected);
+            }
+        }
+        // probing more markers than there are hashes just reads them all first
+        for probe_markers in [1, 2, 3, 10, 0] {
+            bfield.set_probe_markers(probe_markers);
+            assert_eq!(bfield.get_many(&keys), expected);
+            for (key, value) in keys.iter().zip(&expected) {
+                assert_eq!(bfield.get(key), *value);
+            }
+        }
+        assert_eq!(expected[..100].iter().filter(|v| v.is_some()).count(), 100);
+    }
+
+    #[test]
+    fn can_debug_print() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        let debug = format!("{bfield:?}");
+        assert!(debug.starts_with("BField { n_members: 2, read_only: false"));
+        assert!(debug.contains("other: Some(\"extra\")"));
+        assert!(debug.contains("size: 100000, n_hashes: 3, marker_width: 64, n_marker_bits: 4"));
+        assert!(debug.contains("size: 10000,"));
+        assert!(debug.len() < 1_000);
+    }
+
+    #[test]
+    fn can_summarize() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 1, 0);
+        assert_eq!(
+            bfield.summary(),
+            format!(
+                "B-field with 2 arrays of 110000 bits in total ({} bytes with headers), 3 hashes, \
+             64-bit markers with 4 bits set: #0 100000 bits 0.0% full, #1 10000 bits 0.0% full",
+                bfield.memory_bytes()
+            )
+        );
+
+        let histograms = bfield.popcount_histograms(Some(1_000));
+        assert_eq!(histograms.len(), 2);
+        assert_eq!(histograms[0].iter().sum::<u64>(), 1_000);
+        assert_eq!(bfield.popcount_histograms(None)[1][0], 10_000 / 64);
+        assert!(bfield.iter_markers(0).any(|(_, value)| value == 1));
+        assert!(bfield.rank(0, 0..100_000) <= 12);
+        let first = bfield.select(0, 0, 0).unwrap();
+        assert_eq!(bfield.rank(0, 0..first + 1), 1);
+        assert_eq!(bfield.select(1, 0, 0), None);
+        assert_eq!(bfield.iter_markers(1).count(), 0);
+    }
+
+    #[test]
+    fn can_load_expecting_params() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create_with_seed(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            42,
             String::new(),
         )
         .expect("to build");
+        drop(bfield);
 
-        // Identity database
-        let max_value: u32 = 10_000;
-        for p in 0..n_secondaries {
-            for i in 0..max_value {
-                bfield.insert(&i.to_be_bytes().to_vec(), i, p as usize);
+        let path = tmp_dir.path().join("bfield.0.bfd");
+        let expected = ExpectedParams {
+            n_hashes: 3,
+            marker_width: 64,
+            n_marker_bits: 4,
+            seed: 42,
+        };
+        assert!(BField::<String>::load_expecting(&path, true, expected).is_ok());
+        for wrong in [
+            ExpectedParams {
+                n_hashes: 2,
+                ..expected
+            },
+            ExpectedParams {
+                n_marker_bits: 3,
+                ..expected
+            },
+            ExpectedParams {
+                seed: 0,
+                ..expected
+            },
+        ] {
+            let err = BField::<String>::load_expecting(&path, true, wrong)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn can_verify_checksums() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_001,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            true,
+            String::from("extra"),
+        )
+        .expect("to build");
+        for i in 0..1_000u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        assert_eq!(bfield.verify(), Err(ChecksumError::Missing));
+        let bfield = bfield.persist_to_disk().unwrap();
+        assert_eq!(bfield.verify(), Ok(()));
+        drop(bfield);
+
+        let path = tmp_dir.path().join("bfield.0.bfd");
+        let bfield = BField::<String>::load_verified(&path, true).unwrap();
+        assert_eq!(bfield.get(5u64.to_be_bytes()), Some(5));
+        drop(bfield);
+
+        // flip a byte at the end of the file, in the bits of the array
+        let mut bytes = std::fs::read(&path).unwrap();
+        *bytes.last_mut().unwrap() ^= 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+        let bfield = BField::<String>::load(&path, true).unwrap();
+        assert!(matches!(
+            bfield.verify(),
+            Err(ChecksumError::Mismatch { .. })
+        ));
+        let err = BField::<String>::load_verified(&path, true).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let create = |marker_width: u8, n_marker_bits: u8, secondary_scaledown: f64| {
+            BField::create(
+                tmp_dir.path(),
+                "bfield",
+                1024,
+                3,
+                marker_width,
+                n_marker_bits,
+                secondary_scaledown,
+                0.025,
+                2,
+                false,
+                String::new(),
+            )
+        };
+        for res in [create(200, 4, 0.1), create(16, 20, 0.1), create(64, 4, 0.)] {
+            assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(!tmp_dir.path().join("bfield.0.bfd").exists());
+        assert!(create(64, 4, 0.1).is_ok());
+
+        // lots of aggressively scaled down secondaries end up narrower than a marker
+        let err = BField::create(
+            tmp_dir.path(),
+            "scaled",
+            10_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.001,
+            8,
+            false,
+            String::new(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("smallest secondary array"));
+        assert!(!tmp_dir.path().join("scaled.0.bfd").exists());
+    }
+
+    #[test]
+    fn rejects_invalid_filenames() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        for filename in ["", ".", "..", "sub/bfield"] {
+            let err = BField::create(
+                tmp_dir.path(),
+                filename,
+                100_000,
+                3,
+                64,
+                4,
+                0.1,
+                0.025,
+                2,
+                false,
+                String::new(),
+            )
+            .err()
+            .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(err.to_string().contains("without any directory"));
+        }
+        let err = BFieldBuilder::<String>::new(100_000)
+            .build(tmp_dir.path().join(".."))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn can_convert_errors() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let load = |name: &str| BField::<String>::load(tmp_dir.path().join(name), true);
+        let err = BFieldError::from(load("bfield.1.bfd").err().unwrap());
+        assert!(matches!(err, BFieldError::InvalidParams(_)));
+        let err = BFieldError::from(load("missing.0.bfd").err().unwrap());
+        assert!(matches!(err, BFieldError::Io(_)));
+
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 1, 0);
+        drop(bfield);
+        let mut bfield = load("bfield.0.bfd").unwrap();
+        let err = BFieldError::from(bfield.try_insert(b"test", 1, 0).err().unwrap());
+        assert!(matches!(err, BFieldError::ReadOnly));
+        let err = BFieldError::from(bfield.clear().err().unwrap());
+        assert!(matches!(err, BFieldError::ReadOnly));
+        let err = BFieldError::from(bfield.force_insert_checked(b"test", 1).err().unwrap());
+        assert!(matches!(err, BFieldError::ReadOnly));
+        let err = BFieldError::from(bfield.verify().err().unwrap());
+        assert!(matches!(err, BFieldError::Corrupt(_)));
+
+        // only the errors raised by the crate get a variant, whatever their kind
+        let err = io::Error::new(io::ErrorKind::PermissionDenied, "not ours");
+        assert!(matches!(BFieldError::from(err), BFieldError::Io(_)));
+        // and they go back and forth through `io::Error`
+        let err = io::Error::from(BFieldError::ReadOnly);
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert!(matches!(BFieldError::from(err), BFieldError::ReadOnly));
+    }
+
+    #[test]
+    fn get_checked_distinguishes_indeterminate() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // comically small bfield with too many hashes and bits to cause saturation
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            128,
+            16,
+            64,
+            8,
+            0.1,
+            0.025,
+            1,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 100, 0);
+        assert_eq!(bfield.get_checked(b"test"), Err(Indeterminate));
+        assert_eq!(bfield.lookup(b"test"), BFieldLookup::Indeterminate);
+        assert_eq!(bfield.get(b"test"), None);
+        assert!(!bfield.contains_key(b"test"));
+
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1024,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            1,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        bfield.insert(b"test", 100, 0);
+        assert_eq!(bfield.get_checked(b"test"), Ok(Some(100)));
+        assert_eq!(bfield.get_checked(b"test2"), Ok(None));
+        assert_eq!(bfield.lookup(b"test"), BFieldLookup::Some(100));
+        assert_eq!(bfield.lookup(b"test2"), BFieldLookup::None);
+        assert!(bfield.contains_key(b"test"));
+        assert!(!bfield.contains_key(b"test2"));
+        assert_eq!(bfield.get_raw_markers(b"test"), vec![(rank(100, 4), 4)]);
+    }
+
+    #[test]
+    fn can_store_values_above_u32_max() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        // C(64, 8) = 4_426_165_368 so values past u32::MAX are encodable
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            3,
+            64,
+            8,
+            0.1,
+            0.025,
+            1,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        let big = u64::from(u32::MAX) + 1;
+        let max = 4_426_165_367u64;
+        assert!(bfield.try_insert(b"big", big, 0).unwrap());
+        assert!(bfield.try_insert(b"max", max, 0).unwrap());
+        assert_eq!(bfield.get(b"big"), Some(big));
+        assert_eq!(bfield.get(b"max"), Some(max));
+        assert!(bfield.try_insert(b"too big", max + 1, 0).is_err());
+    }
+
+    #[test]
+    fn can_query_from_several_threads() {
+        fn assert_send_sync<S: Send + Sync>() {}
+        assert_send_sync::<BField<String>>();
+
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            1_000_000,
+            3,
+            64,
+            4,
+            0.1,
+            0.025,
+            2,
+            false,
+            String::new(),
+        )
+        .expect("to build");
+        for pass in 0..2 {
+            for i in 0..1_000u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
             }
         }
+        drop(bfield);
 
-        for i in 0..max_value {
-            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
-            assert_eq!(i, val);
+        let bfield: Arc<BField<String>> =
+            Arc::new(BField::load(tmp_dir.path().join("bfield.0.bfd"), true).expect("to load"));
+        bfield.advise(AccessPattern::Random).unwrap();
+        bfield.preload().unwrap();
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let bfield = Arc::clone(&bfield);
+                thread::spawn(move || {
+                    (t * 250..(t + 1) * 250)
+                        .filter(|i| bfield.get(i.to_be_bytes()) == Some(*i))
+                        .count()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), 250);
         }
-        bfield = bfield.persist_to_disk().unwrap();
-        for m in &bfield.members {
-            assert!(m.filename.exists());
+    }
+
+    #[test]
+    fn can_insert_from_several_threads() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield: Arc<BField<String>> = Arc::new(
+            BField::create(
+                tmp_dir.path(),
+                "bfield",
+                10_000_000,
+                3,
+                64,
+                4,
+                0.1,
+                0.025,
+                2,
+                false,
+                String::new(),
+            )
+            .expect("to build"),
+        );
+        let n_threads = 8u64;
+        let per_thread = 2_000u64;
+        for pass in 0..2 {
+            let handles: Vec<_> = (0..n_threads)
+                .map(|t| {
+                    let bfield = Arc::clone(&bfield);
+                    thread::spawn(move || {
+                        for i in t * per_thread..(t + 1) * per_thread {
+                            bfield.try_insert_atomic(i.to_be_bytes(), i, pass).unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
         }
-        for i in 0..max_value {
-            let val = bfield.get(&i.to_be_bytes().to_vec()).unwrap();
-            assert_eq!(i, val);
+        assert_eq!(bfield.len(), n_threads * per_thread);
+        for i in 0..n_threads * per_thread {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
         }
+        assert_eq!(
+            bfield.try_insert_atomic(b"key", 0, 2),
+            Err(InsertError::InvalidPass {
+                pass: 2,
+                n_members: 2
+            })
+        );
     }
 }
 
@@ -1,14 +1,21 @@
 use std::cell::UnsafeCell;
 use std::cmp::Ordering;
-#[cfg(feature = "prefetching")]
-use std::intrinsics;
-use std::io;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU64;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{self, AtomicU64, AtomicU8};
 
-use crate::combinatorial::{rank, unrank};
-use bincode::{deserialize, serialize};
+use crate::combinatorial::{
+    checked_choose, checked_unrank, max_value, rank_table, RankTable, MARKER_TABLE_SIZE,
+};
+use crate::error::{BFieldError, ChecksumError, InsertError, MergeError};
+use crate::hasher::{BFieldHasher, Murmur3};
+use bincode::Options;
 use mmap_bitvec::{BitVector, MmapBitVec};
-use murmurhash3::murmurhash3_x64_128;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
@@ -27,53 +34,693 @@ impl BitVec {
     pub fn get(&self) -> &mut MmapBitVec {
         unsafe { &mut *self.bv.get() }
     }
+
+    /// Same as `get_range` for ranges of at most 64 bits, read straight from the mapping
+    /// as one big-endian word (plus a byte for unaligned starts) rather than bit by bit
+    /// into a `u128`. Ranges too close to the end of the array fall back to `get_range`.
+    #[inline]
+    pub fn get_range_u64(&self, r: std::ops::Range<usize>) -> u64 {
+        let width = r.end - r.start;
+        debug_assert!(width > 0 && width <= 64 && r.end <= self.get().size());
+        let first = r.start >> 3;
+        let shift = r.start & 7;
+        match self.get().mmap.get(first..first + 9) {
+            Some(window) => {
+                let mut word = [0u8; 8];
+                word.copy_from_slice(&window[..8]);
+                let mut bits = u64::from_be_bytes(word) << shift;
+                if shift > 0 {
+                    bits |= u64::from(window[8]) >> (8 - shift);
+                }
+                bits >> (64 - width)
+            }
+            None => self.get().get_range(r) as u64,
+        }
+    }
+
+    /// Same as `set_range` but sets the bits with atomic ORs, so it's safe to call from
+    /// several threads at once even when the ranges share bytes. This follows the
+    /// `MmapBitVec` layout: bits are stored most significant first in each byte and the
+    /// most significant bit of `x` ends up at `r.start`.
+    pub fn set_range_atomic(&self, r: std::ops::Range<usize>, x: u128) {
+        debug_assert!(r.end <= self.get().size());
+        let ptr = self.get().mmap.as_mut_ptr();
+        let mut bits = x & (u128::MAX >> (128 - (r.end - r.start)));
+        while bits != 0 {
+            let pos = r.end - 1 - bits.trailing_zeros() as usize;
+            bits &= bits - 1;
+            // AtomicU8 has the same in-memory representation as u8
+            let byte = unsafe { &*(ptr.add(pos >> 3) as *const AtomicU8) };
+            byte.fetch_or(1 << (7 - (pos & 7)), atomic::Ordering::Relaxed);
+        }
+    }
 }
 
+// The mapping is only reached through `get`, so sharing it is fine for concurrent reads.
+// Writes through `&self` still need the caller to rule out concurrent access (see the
+// `BField` docs), except for `set_range_atomic` which only races with other atomic writes.
 unsafe impl Send for BitVec {}
 unsafe impl Sync for BitVec {}
 
-// Empty function on some archs
+// Prefetches with stable intrinsics or assembly on x86/x86_64 (with SSE) and aarch64, so
+// no nightly feature is needed; an empty function on other archs
+#[allow(unused_variables)]
+#[inline]
+fn prefetch_read(pointer: *const u8, locality: PrefetchLocality) {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse"
+    ))]
+    {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86 as arch_impl;
+        #[cfg(target_arch = "x86_64")]
+        use std::arch::x86_64 as arch_impl;
+
+        let pointer = pointer as *const i8;
+        unsafe {
+            match locality {
+                PrefetchLocality::NonTemporal => {
+                    arch_impl::_mm_prefetch::<{ arch_impl::_MM_HINT_NTA }>(pointer)
+                }
+                PrefetchLocality::Low => {
+                    arch_impl::_mm_prefetch::<{ arch_impl::_MM_HINT_T2 }>(pointer)
+                }
+                PrefetchLocality::Medium => {
+                    arch_impl::_mm_prefetch::<{ arch_impl::_MM_HINT_T1 }>(pointer)
+                }
+                PrefetchLocality::High => {
+                    arch_impl::_mm_prefetch::<{ arch_impl::_MM_HINT_T0 }>(pointer)
+                }
+            }
+        }
+    }
+    // `core::arch::aarch64::_prefetch` isn't stable yet but `prfm` is all it does
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        match locality {
+            PrefetchLocality::NonTemporal => std::arch::asm!(
+                "prfm pldl1strm, [{0}]",
+                in(reg) pointer,
+                options(nostack, readonly, preserves_flags)
+            ),
+            PrefetchLocality::Low => std::arch::asm!(
+                "prfm pldl3keep, [{0}]",
+                in(reg) pointer,
+                options(nostack, readonly, preserves_flags)
+            ),
+            PrefetchLocality::Medium => std::arch::asm!(
+                "prfm pldl2keep, [{0}]",
+                in(reg) pointer,
+                options(nostack, readonly, preserves_flags)
+            ),
+            PrefetchLocality::High => std::arch::asm!(
+                "prfm pldl1keep, [{0}]",
+                in(reg) pointer,
+                options(nostack, readonly, preserves_flags)
+            ),
+        }
+    }
+}
+
+// Same as `prefetch_read` but hints that the cache line is about to be written, which
+// only has one locality level
 #[allow(unused_variables)]
 #[inline]
-fn prefetch_read(pointer: *const u8) {
-    #[cfg(all(target_arch = "x86_64", target_feature = "sse"))]
+fn prefetch_write(pointer: *const u8, locality: PrefetchLocality) {
+    #[cfg(all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse"
+    ))]
     {
+        #[cfg(target_arch = "x86")]
+        use std::arch::x86 as arch_impl;
+        #[cfg(target_arch = "x86_64")]
         use std::arch::x86_64 as arch_impl;
 
         unsafe {
-            arch_impl::_mm_prefetch::<{ arch_impl::_MM_HINT_NTA }>(pointer as *const i8);
+            arch_impl::_mm_prefetch::<{ arch_impl::_MM_HINT_ET0 }>(pointer as *const i8);
         }
     }
+    #[cfg(target_arch = "aarch64")]
+    unsafe {
+        std::arch::asm!(
+            "prfm pstl1keep, [{0}]",
+            in(reg) pointer,
+            options(nostack, readonly, preserves_flags)
+        );
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct BFieldParams<T> {
-    n_hashes: u8,      // k
-    marker_width: u8,  // nu
-    n_marker_bits: u8, // kappa
+    n_hashes: u8,                 // k
+    marker_width: u8,             // nu
+    pub(crate) n_marker_bits: u8, // kappa
     pub(crate) other: Option<T>,
+    // Seed of the murmur hash
+    pub(crate) seed: u64,
+    // Whether marker positions are reduced with `fastrange` rather than a modulo,
+    // only false for unversioned files
+    pub(crate) fastrange: bool,
+    // How the member sizes were derived, only kept in the primary array
+    pub(crate) secondaries: Option<SecondaryParams>,
+    // `BFieldHasher::name` of the hash function
+    pub(crate) hasher: String,
+    // Murmur3 checksum of the bits as of the last `persist_to_disk`
+    pub(crate) checksum: Option<u64>,
+    // First half of the hash of `HASH_CHECK_KEY` with `seed` when the file was created,
+    // to catch hash functions behaving differently on another platform
+    pub(crate) hash_check: Option<u64>,
+    // Whether each of the `n_hashes` markers of a key goes in its own band of the array,
+    // so they can't overlap
+    pub(crate) disjoint: bool,
+    // How the two halves of the hash are combined into the position of each marker
+    pub(crate) position_scheme: PositionScheme,
+    // Number of keys inserted, only counted in the primary array. It's kept last so it
+    // is the last 8 bytes of the header, which `write_n_inserted` rewrites in place.
+    pub(crate) n_inserted: AtomicU64,
+}
+
+/// The unversioned header layout of files created before any of the other params
+/// existed; those were all built with a seed of 0 and positions reduced with a modulo.
+#[derive(Deserialize)]
+struct LegacyBFieldParams<T> {
+    n_hashes: u8,
+    marker_width: u8,
+    n_marker_bits: u8,
+    other: Option<T>,
+}
+
+// `AtomicU64` isn't `Clone`, so this can't be derived
+impl<T: Clone> Clone for BFieldParams<T> {
+    fn clone(&self) -> Self {
+        BFieldParams {
+            n_hashes: self.n_hashes,
+            marker_width: self.marker_width,
+            n_marker_bits: self.n_marker_bits,
+            other: self.other.clone(),
+            seed: self.seed,
+            fastrange: self.fastrange,
+            secondaries: self.secondaries,
+            hasher: self.hasher.clone(),
+            checksum: self.checksum,
+            n_inserted: AtomicU64::new(self.n_inserted.load(atomic::Ordering::Relaxed)),
+            hash_check: self.hash_check,
+            disjoint: self.disjoint,
+            position_scheme: self.position_scheme,
+        }
+    }
+}
+
+impl<T> BFieldParams<T> {
+    pub(crate) fn new(
+        n_hashes: u8,
+        marker_width: u8,
+        n_marker_bits: u8,
+        seed: u64,
+        other: Option<T>,
+    ) -> Self {
+        BFieldParams {
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            other,
+            seed,
+            fastrange: true,
+            secondaries: None,
+            // filled in by `BFieldMember::create_with_params`
+            hasher: String::new(),
+            checksum: None,
+            n_inserted: AtomicU64::new(0),
+            hash_check: None,
+            disjoint: false,
+            position_scheme: PositionScheme::DoubleHashing,
+        }
+    }
+
+    /// Returns the largest value that can be encoded, which `validate` checked fits in a
+    /// `u64`.
+    fn max_value(&self) -> BFieldVal {
+        max_value(self.marker_width, self.n_marker_bits)
+    }
+
+    /// Checks the parameters are usable, returning the `error` variant otherwise, i.e.
+    /// `InvalidParams` when creating and `Corrupt` when loading.
+    fn validate(&self, error: fn(String) -> BFieldError) -> Result<(), io::Error> {
+        if self.n_hashes == 0 {
+            return Err(io::Error::from(error(
+                "n_hashes needs to be at least 1".to_string(),
+            )));
+        }
+        // markers are read and written as `u128`s
+        if self.marker_width > 128 {
+            return Err(io::Error::from(error(format!(
+                "marker_width is {} but markers are at most 128 bits wide",
+                self.marker_width
+            ))));
+        }
+        if self.n_marker_bits == 0 || self.n_marker_bits > self.marker_width {
+            return Err(io::Error::from(error(format!(
+                "n_marker_bits ({}) needs to be between 1 and marker_width ({})",
+                self.n_marker_bits, self.marker_width
+            ))));
+        }
+        // values are turned into markers with the rank tables, which only go up to 9 bits
+        if self.n_marker_bits >= 10 {
+            return Err(io::Error::from(error(format!(
+                "n_marker_bits is {} but values can only be encoded with fewer than 10",
+                self.n_marker_bits
+            ))));
+        }
+        if checked_choose(u64::from(self.marker_width), self.n_marker_bits).is_none() {
+            return Err(io::Error::from(error(format!(
+                "{} choose {} values don't fit in a u64",
+                self.marker_width, self.n_marker_bits
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Checks markers fit in an array of `size` bits, otherwise reducing hashes to marker
+    /// positions would underflow or divide by zero.
+    fn validate_size(
+        &self,
+        size: usize,
+        error: fn(String) -> BFieldError,
+    ) -> Result<(), io::Error> {
+        let marker_width = self.marker_width as usize;
+        if size <= marker_width {
+            return Err(io::Error::from(error(format!(
+                "size ({}) needs to be greater than marker_width ({})",
+                size, marker_width
+            ))));
+        }
+        if self.disjoint && size / self.n_hashes as usize <= marker_width {
+            return Err(io::Error::from(error(format!(
+                    "size ({}) split into {} bands for disjoint markers leaves no more than marker_width ({}) bits per band",
+                    size, self.n_hashes, marker_width
+                ))));
+        }
+        Ok(())
+    }
+
+    /// Checks the params were written with the given hash function, and that it still
+    /// hashes keys the same way if the header recorded a hash check.
+    fn check_hasher<H: BFieldHasher>(&self, hasher: &H) -> Result<(), io::Error> {
+        if self.hasher != hasher.name() {
+            return Err(io::Error::from(BFieldError::Corrupt(format!(
+                "B-field was built with the {} hasher but opened with {}",
+                self.hasher,
+                hasher.name()
+            ))));
+        }
+        if let Some(hash_check) = self.hash_check {
+            let found = hasher.hash128(HASH_CHECK_KEY, self.seed).0;
+            if found != hash_check {
+                return Err(io::Error::from(BFieldError::Corrupt(format!(
+                    "the {} hasher doesn't give the same hashes as when the B-field was built \
+                         ({:#018x} instead of {:#018x})",
+                    self.hasher, found, hash_check
+                ))));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: Serialize> BFieldParams<T> {
+    /// Serializes the params into a header, prefixed with `HEADER_VERSION` and
+    /// `BYTE_ORDER_MARK`.
+    pub(crate) fn to_header(&self) -> Result<Vec<u8>, io::Error> {
+        let mut header = HEADER_VERSION.to_be_bytes().to_vec();
+        header.extend_from_slice(&BYTE_ORDER_MARK.to_le_bytes());
+        let params = bincode_options()
+            .serialize(self)
+            .map_err(|e| io::Error::from(BFieldError::InvalidParams(e.to_string())))?;
+        header.extend_from_slice(&params);
+        Ok(header)
+    }
+}
+
+impl<T: DeserializeOwned> BFieldParams<T> {
+    /// Reads the params back from a header, accepting the unversioned layout written
+    /// by older releases and rejecting unknown versions.
+    fn from_header(header: &[u8]) -> Result<Self, io::Error> {
+        let params = if header.first() == Some(&0) {
+            if header.len() < 2 {
+                return Err(io::Error::from(BFieldError::Corrupt(
+                    "truncated B-field header".to_string(),
+                )));
+            }
+            match u16::from_be_bytes([header[0], header[1]]) {
+                HEADER_VERSION => {
+                    check_byte_order(&header[2..])?;
+                    bincode_options().deserialize(&header[6..])
+                }
+                version => {
+                    return Err(io::Error::from(BFieldError::Corrupt(format!(
+                        "unsupported B-field header version {} (expected {})",
+                        version, HEADER_VERSION
+                    ))));
+                }
+            }
+        } else {
+            bincode_options()
+                .deserialize::<LegacyBFieldParams<T>>(header)
+                .map(Into::into)
+        };
+        params.map_err(|e| io::Error::from(BFieldError::Deserialize(e.to_string())))
+    }
+}
+
+/// The bincode settings of the headers, spelled out so that they can't change under us:
+/// integers are written with a fixed size and little-endian whatever the platform,
+/// which is what `bincode::serialize` always did.
+fn bincode_options() -> impl Options + Copy {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .allow_trailing_bytes()
+}
+
+/// Checks the header starts with `BYTE_ORDER_MARK` written little-endian, to reject
+/// headers from writers that didn't stick to `bincode_options`.
+fn check_byte_order(header: &[u8]) -> Result<(), io::Error> {
+    let mark = match header.get(..4) {
+        Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        None => {
+            return Err(io::Error::from(BFieldError::Corrupt(
+                "truncated B-field header".to_string(),
+            )))
+        }
+    };
+    if mark == BYTE_ORDER_MARK.swap_bytes() {
+        return Err(io::Error::from(BFieldError::Corrupt(
+            "B-field header was written big-endian".to_string(),
+        )));
+    } else if mark != BYTE_ORDER_MARK {
+        return Err(io::Error::from(BFieldError::Corrupt(format!(
+            "invalid B-field byte order mark {:#010x}",
+            mark
+        ))));
+    }
+    Ok(())
 }
 
-pub(crate) struct BFieldMember<T> {
+impl<T> From<LegacyBFieldParams<T>> for BFieldParams<T> {
+    fn from(params: LegacyBFieldParams<T>) -> Self {
+        BFieldParams {
+            n_hashes: params.n_hashes,
+            marker_width: params.marker_width,
+            n_marker_bits: params.n_marker_bits,
+            other: params.other,
+            seed: 0,
+            fastrange: false,
+            secondaries: None,
+            hasher: Murmur3.name().to_string(),
+            checksum: None,
+            n_inserted: AtomicU64::new(0),
+            hash_check: None,
+            disjoint: false,
+            position_scheme: PositionScheme::DoubleHashing,
+        }
+    }
+}
+
+pub(crate) struct BFieldMember<T, H = Murmur3> {
     bitvec: BitVec,
     // Used when loading mmap in memory to know where to save it if needed
     pub(crate) filename: PathBuf,
     pub(crate) params: BFieldParams<T>,
+    pub(crate) hasher: H,
+    read_only: bool,
+    prefetch_locality: PrefetchLocality,
+    prefetch_distance: usize,
+    probe_markers: usize,
+    // Turns values into markers for inserts. Read-only members never need it, so they
+    // don't build it (it's 3.2 MB for 3 bits or more).
+    rank_table: Option<&'static RankTable>,
+    // `C(marker_width, n_marker_bits) - 1`, computed once rather than on every lookup
+    max_value: BFieldVal,
+}
+
+// Leaves the bits out, which would be way too long to print
+impl<T: fmt::Debug, H: BFieldHasher> fmt::Debug for BFieldMember<T, H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BFieldMember")
+            .field("filename", &self.filename)
+            .field("size", &self.bitvec.get().size())
+            .field("n_hashes", &self.params.n_hashes)
+            .field("marker_width", &self.params.marker_width)
+            .field("n_marker_bits", &self.params.n_marker_bits)
+            .field("seed", &self.params.seed)
+            .field("hasher", &self.hasher.name())
+            .field("read_only", &self.read_only)
+            .field("other", &self.params.other)
+            .finish()
+    }
 }
 
 /// A simple type alias to make the code more readable
-pub type BFieldVal = u32;
+pub type BFieldVal = u64;
+
+/// A value that can't be 0, for `BField`s where 0 is reserved as a sentinel (e.g. an
+/// unassigned taxonomy ID), see `BField::insert_nonzero` and `BField::get_nonzero`.
+pub type NonZeroBFieldVal = NonZeroU64;
+
+/// The parameters of one of the bit arrays of a `BField`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemberInfo {
+    /// The size of the array, in bits
+    pub size: usize,
+    /// The number of hash functions _k_
+    pub n_hashes: u8,
+    /// The length ν (nu) of each marker
+    pub marker_width: u8,
+    /// The number κ (kappa) of 1s in each marker
+    pub n_marker_bits: u8,
+}
+
+/// The parameters a `BField` is expected to have been built with, to catch loading the
+/// wrong file, see `BField::load_expecting`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedParams {
+    /// The number of hash functions _k_
+    pub n_hashes: u8,
+    /// The length ν (nu) of each marker
+    pub marker_width: u8,
+    /// The number κ (kappa) of 1s in each marker
+    pub n_marker_bits: u8,
+    /// The seed of the hash function
+    pub seed: u64,
+}
+
+/// The parameters that determined the sizes of the secondary arrays of a `BField`,
+/// see `BField::create`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct SecondaryParams {
+    /// The scaling factor β (beta) of each secondary array size
+    pub secondary_scaledown: f64,
+    /// The smallest a secondary array could get relative to the primary one
+    pub max_scaledown: f64,
+    /// The number of arrays, including the primary one
+    pub n_secondaries: u8,
+}
+
+/// Everything needed to build an empty `BField` laid out like another one, in a form
+/// that can be written as TOML or JSON to document how a `BField` was built, rebuild
+/// it reproducibly or compare the configurations of two of them. Unlike the binary
+/// headers of the arrays this holds no data, so it doesn't change as keys get inserted.
+///
+/// See `BField::config` and `BField::create_from_config`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct BFieldConfig {
+    /// The size of each array in bits, starting with the primary one
+    pub sizes: Vec<usize>,
+    /// The number of hash functions _k_
+    pub n_hashes: u8,
+    /// The length ν (nu) of each marker
+    pub marker_width: u8,
+    /// The number κ (kappa) of 1s in each marker
+    pub n_marker_bits: u8,
+    /// The seed of the hash function
+    pub seed: u64,
+    /// The name of the hash function, see `BFieldHasher::name`
+    pub hasher: String,
+    /// Whether marker positions are reduced with `fastrange` rather than a modulo, which
+    /// is only false for `BField`s written by older versions
+    pub fastrange: bool,
+    /// Whether each marker of a key goes in its own band of the arrays
+    pub disjoint: bool,
+    /// How marker positions are derived from the hash of a key
+    pub position_scheme: PositionScheme,
+    /// The parameters the sizes of the secondary arrays were derived from, if known
+    pub secondaries: Option<SecondaryParams>,
+}
+
+/// How the bit arrays are going to be accessed, passed on to the OS (via `madvise`)
+/// to tune readahead. This is a no-op on non-unix platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessPattern {
+    /// No particular pattern, the OS default.
+    Normal,
+    /// Pages are touched in random order (lookups), so readahead is wasted.
+    Random,
+    /// Pages are read in order, so aggressive readahead helps.
+    Sequential,
+    /// The whole array will be needed soon and can be paged in ahead of time.
+    WillNeed,
+}
+
+#[cfg(unix)]
+impl From<AccessPattern> for memmap2::Advice {
+    fn from(pattern: AccessPattern) -> Self {
+        match pattern {
+            AccessPattern::Normal => memmap2::Advice::Normal,
+            AccessPattern::Random => memmap2::Advice::Random,
+            AccessPattern::Sequential => memmap2::Advice::Sequential,
+            AccessPattern::WillNeed => memmap2::Advice::WillNeed,
+        }
+    }
+}
+
+/// How the two 64-bit halves `h1` and `h2` of the hash of a key are combined into the
+/// position of its `n`th marker, before being reduced to the size of the array.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PositionScheme {
+    /// `h1 + n * h2`, the classic double hashing. The first marker (`n = 0`) is placed
+    /// with `h1` alone, so with `n_hashes = 1` half of the hash goes unused.
+    #[default]
+    DoubleHashing,
+    /// `h1 + n * h2 + n * (n + 1) * (n + 2) / 6` markers apart, i.e. enhanced double
+    /// hashing's `(m³ - m) / 6` with `m = n + 1` so the first two markers differ too.
+    /// The extra term is added to the hash before it's reduced and scaled to whole
+    /// markers, so the markers of a key can't overlap even when `h2` is (almost) 0,
+    /// which makes it cluster less with many hashes
+    EnhancedDoubleHashing,
+    /// `(h1 ^ h2) + n * h2`, which places every marker, the first one included, with
+    /// both halves of the hash; mostly useful with `n_hashes = 1`
+    MixedDoubleHashing,
+}
+
+/// Which cache levels marker positions are prefetched into ahead of being read, from
+/// `NonTemporal` (`_MM_HINT_NTA`, least cache pollution) to `High` (`_MM_HINT_T0`, every
+/// level). Lookups that never come back to the same markers are best served by
+/// `NonTemporal`, the default. Prefetching is done on x86/x86_64 (with SSE) and aarch64
+/// only, and is a no-op elsewhere.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PrefetchLocality {
+    /// Don't keep the data around once read
+    #[default]
+    NonTemporal,
+    /// Keep it in the outermost cache level only
+    Low,
+    /// Keep it in the second level cache and above
+    Medium,
+    /// Keep it in every cache level
+    High,
+}
 /// Magic bytes used to indicate the `bfield` file type for `MmapBitvec`
 const BF_MAGIC: [u8; 2] = [0xBF, 0x1D];
+/// Version of the header layout, stored big-endian in front of the serialized params.
+/// Unversioned headers start with `n_hashes` (never 0), so a leading 0 byte marks a
+/// versioned one as long as this stays below 256.
+const HEADER_VERSION: u16 = 1;
+/// Written little-endian right after the header version, to detect headers written with
+/// another byte order
+const BYTE_ORDER_MARK: u32 = 0x0102_0304;
+/// Hashed with the seed of each member to fill in `BFieldParams::hash_check`
+const HASH_CHECK_KEY: &[u8] = b"bfield hash check";
+/// Stride of the reads of `preload`; smaller than the page size of most platforms, which
+/// only means some pages are read twice
+const PAGE_SIZE: usize = 4096;
+/// Number of intervals the values are sampled at by `check_encoding`
+const ENCODING_SAMPLES: u128 = 64;
+/// Number of marker positions `get_raw` can hold without allocating
+const STACK_POSITIONS: usize = 16;
+/// Whether `prefetch_read` does anything on this target
+const PREFETCHES: bool = cfg!(any(
+    all(
+        any(target_arch = "x86", target_arch = "x86_64"),
+        target_feature = "sse"
+    ),
+    target_arch = "aarch64"
+));
+/// How many keys ahead `insert_many` and `get_many` prefetch marker positions by default
+const PREFETCH_DISTANCE: usize = 4;
+
+/// What `mask_or_insert` did with a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MaskOutcome {
+    /// The key wasn't present and got inserted
+    Inserted,
+    /// The key was already present with the same value
+    AlreadyPresent,
+    /// The key was present with a different value, which got masked to indeterminate
+    Masked,
+    /// The key was already indeterminate
+    Indeterminate,
+}
 
-#[derive(Debug, PartialEq)]
-pub(crate) enum BFieldLookup {
+/// The result of looking up a key, see `BField::lookup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BFieldLookup {
+    /// Too many bits were set to tell the value apart
     Indeterminate,
+    /// The key was found with this value
     Some(BFieldVal),
+    /// The key isn't present (barring a false negative from `force_insert`)
     None,
 }
 
-impl<T: Clone + DeserializeOwned + Serialize> BFieldMember<T> {
+impl<T, H> BFieldMember<T, H> {
+    /// Opens the file backing the member to update its header in place, positioned right
+    /// after the `BF_MAGIC` and header length it starts with. Returns `None` for read-only
+    /// and in-memory members and for unversioned headers, which are left alone.
+    fn open_header(&self) -> Result<Option<fs::File>, io::Error> {
+        let header = self.bitvec.get().header();
+        if self.read_only || header.first() != Some(&0) {
+            return Ok(None);
+        }
+        // the file starts with `BF_MAGIC` and the big-endian length of the header
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&self.filename)?;
+        let mut prefix = [0u8; 4];
+        file.read_exact(&mut prefix)?;
+        if prefix[..2] != BF_MAGIC
+            || usize::from(u16::from_be_bytes([prefix[2], prefix[3]])) != header.len()
+        {
+            return Err(io::Error::from(BFieldError::Corrupt(format!(
+                "{:?} doesn't start with the header it was opened with",
+                self.filename
+            ))));
+        }
+        Ok(Some(file))
+    }
+
+    /// Rewrites the insert count at the end of the header in place, so that it's there
+    /// when the file is opened again without `persist_to_disk` copying the whole array.
+    /// Does nothing for read-only and in-memory members, nor for unversioned headers,
+    /// which have no count.
+    pub(crate) fn write_n_inserted(&self) -> Result<(), io::Error> {
+        let mut file = match self.open_header()? {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        let n_inserted = self.params.n_inserted.load(atomic::Ordering::Relaxed);
+        file.seek(SeekFrom::Current(
+            self.bitvec.get().header().len() as i64 - 8,
+        ))?;
+        file.write_all(&n_inserted.to_le_bytes())
+    }
+}
+
+impl<T: Clone + DeserializeOwned + Serialize, H: BFieldHasher> BFieldMember<T, H> {
+    #[cfg(test)]
+    #[allow(clippy::too_many_arguments)]
     pub fn create<P: AsRef<Path>>(
         filename: P,
         in_memory: bool,
@@ -81,72 +728,523 @@ impl<T: Clone + DeserializeOwned + Serialize> BFieldMember<T> {
         n_hashes: u8,
         marker_width: u8,
         n_marker_bits: u8,
+        seed: u64,
         other_params: Option<T>,
-    ) -> Result<Self, io::Error> {
-        let bf_params = BFieldParams {
-            n_hashes,
-            marker_width,
-            n_marker_bits,
-            other: other_params,
+    ) -> Result<Self, io::Error>
+    where
+        H: Default,
+    {
+        let bf_params =
+            BFieldParams::new(n_hashes, marker_width, n_marker_bits, seed, other_params);
+        Self::create_with_params(filename, in_memory, size, bf_params, H::default())
+    }
+
+    /// Rewrites the whole header in place like `write_n_inserted`, e.g. after changing
+    /// the `n_secondaries` of the primary array. The params need to serialize to as many
+    /// bytes as the header on disk, which holds as long as `other` is left alone.
+    pub(crate) fn write_header(&self) -> Result<(), io::Error> {
+        let mut file = match self.open_header()? {
+            Some(file) => file,
+            None => return Ok(()),
         };
+        let header = self.params.to_header()?;
+        if header.len() != self.bitvec.get().header().len() {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "the new header of {:?} doesn't fit in place of the old one",
+                self.filename
+            ))));
+        }
+        file.write_all(&header)
+    }
+
+    /// Creates a member of `size` bits with the given params, backed by `filename`
+    /// unless `in_memory` is set.
+    pub(crate) fn create_with_params<P: AsRef<Path>>(
+        filename: P,
+        in_memory: bool,
+        size: usize,
+        mut bf_params: BFieldParams<T>,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
+        bf_params.hasher = hasher.name().to_string();
+        bf_params.hash_check = Some(hasher.hash128(HASH_CHECK_KEY, bf_params.seed).0);
+        bf_params.validate(BFieldError::InvalidParams)?;
+        bf_params.validate_size(size, BFieldError::InvalidParams)?;
 
         let bv = if in_memory {
             MmapBitVec::from_memory(size)?
         } else {
-            let header: Vec<u8> = serialize(&bf_params).unwrap();
+            let header = bf_params.to_header()?;
             MmapBitVec::create(&filename, size, Some(BF_MAGIC), &header)?
         };
 
         Ok(BFieldMember {
             filename: filename.as_ref().to_path_buf(),
             bitvec: BitVec::new(bv),
+            rank_table: writable_rank_table(false, bf_params.n_marker_bits),
+            max_value: bf_params.max_value(),
             params: bf_params,
+            hasher,
+            read_only: false,
+            prefetch_locality: PrefetchLocality::default(),
+            prefetch_distance: PREFETCH_DISTANCE,
+            probe_markers: 0,
         })
     }
 
-    pub fn open<P: AsRef<Path>>(filename: P, read_only: bool) -> Result<Self, io::Error> {
+    pub fn open<P: AsRef<Path>>(
+        filename: P,
+        read_only: bool,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
         let bv = MmapBitVec::open(&filename, Some(&BF_MAGIC), read_only)?;
-        let bf_params: BFieldParams<T> = {
-            let header = bv.header();
-            deserialize(header).unwrap()
-        };
+        let bf_params = BFieldParams::<T>::from_header(bv.header())?;
+        bf_params.validate(BFieldError::Corrupt)?;
+        bf_params.validate_size(bv.size(), BFieldError::Corrupt)?;
+        bf_params.check_hasher(&hasher)?;
 
         Ok(BFieldMember {
             filename: filename.as_ref().to_path_buf(),
             bitvec: BitVec::new(bv),
+            rank_table: writable_rank_table(read_only, bf_params.n_marker_bits),
+            max_value: bf_params.max_value(),
             params: bf_params,
+            hasher,
+            read_only,
+            prefetch_locality: PrefetchLocality::default(),
+            prefetch_distance: PREFETCH_DISTANCE,
+            probe_markers: 0,
         })
     }
 
-    pub fn persist_to_disk(self) -> Result<Self, io::Error> {
-        let header: Vec<u8> = serialize(&self.params).unwrap();
+    /// Saves the member to `filename` along with a checksum of its bits, for members
+    /// built in memory as well as to seal ones built on disk.
+    pub fn persist_to_disk(mut self) -> Result<Self, io::Error> {
+        self.params.checksum = Some(self.checksum());
+        let header = self.params.to_header()?;
+        // `filename` may well be the file we have mapped so write next to it and
+        // only swap it in once the mapping is gone
+        let mut tmp_filename = self.filename.clone().into_os_string();
+        tmp_filename.push(".tmp");
         self.bitvec
             .get()
-            .save_to_disk(&self.filename, Some(BF_MAGIC), &header)?;
+            .save_to_disk(&tmp_filename, Some(BF_MAGIC), &header)?;
+        drop(self.bitvec);
+        fs::rename(&tmp_filename, &self.filename)?;
+
         let bitvec = BitVec::new(MmapBitVec::open(&self.filename, Some(&BF_MAGIC), false)?);
         Ok(Self {
             bitvec,
             filename: self.filename,
+            rank_table: writable_rank_table(false, self.params.n_marker_bits),
+            max_value: self.max_value,
             params: self.params,
+            hasher: self.hasher,
+            read_only: false,
+            prefetch_locality: self.prefetch_locality,
+            prefetch_distance: self.prefetch_distance,
+            probe_markers: self.probe_markers,
+        })
+    }
+
+    /// Moves the bits to a larger array of `new_size` bits, e.g. when a build outgrows
+    /// the size it was given. Marker positions depend on the size so the bits can't
+    /// just be copied over: each of `keys` is looked up here and its marker written at
+    /// its new positions, so every key inserted in this member needs to be in `keys`
+    /// (and other keys cost a lookup for nothing). Keys that are indeterminate here stay
+    /// indeterminate so lookups still fall through to the next member.
+    ///
+    /// File-backed members are rewritten next to their file, which is then swapped for
+    /// the new one, and lose their checksum until they're persisted again.
+    pub fn grow<K, I>(&mut self, new_size: usize, keys: I) -> Result<(), io::Error>
+    where
+        K: AsRef<[u8]>,
+        I: IntoIterator<Item = K>,
+    {
+        if self.read_only {
+            return Err(io::Error::from(BFieldError::ReadOnly));
+        }
+        let size = self.bitvec.get().size();
+        if new_size < size {
+            return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                "new_size ({}) can't be smaller than the current size ({})",
+                new_size, size
+            ))));
+        }
+        let mut params = self.params.clone();
+        params.validate_size(new_size, BFieldError::InvalidParams)?;
+        params.checksum = None;
+        params.n_inserted.store(0, atomic::Ordering::Relaxed);
+
+        // only arrays created on disk have a header
+        let in_memory = self.bitvec.get().header().is_empty();
+        let mut tmp_filename = self.filename.clone().into_os_string();
+        tmp_filename.push(".tmp");
+        let bv = if in_memory {
+            MmapBitVec::from_memory(new_size)?
+        } else {
+            let header = params.to_header()?;
+            MmapBitVec::create(&tmp_filename, new_size, Some(BF_MAGIC), &header)?
+        };
+        let grown = BFieldMember {
+            filename: self.filename.clone(),
+            bitvec: BitVec::new(bv),
+            rank_table: writable_rank_table(false, params.n_marker_bits),
+            max_value: self.max_value,
+            params,
+            hasher: self.hasher.clone(),
+            read_only: false,
+            prefetch_locality: self.prefetch_locality,
+            prefetch_distance: self.prefetch_distance,
+            probe_markers: self.probe_markers,
+        };
+
+        let k = u32::from(self.params.n_marker_bits);
+        let mut n_copied = 0;
+        for key in keys {
+            let hash = self.hash_key(key.as_ref());
+            // absent keys merge to 0, present ones to exactly `k` bits and indeterminate
+            // ones to more, which are written as is to keep them indeterminate
+            let marker = self.get_hashed(hash, k);
+            if marker.count_ones() >= k {
+                grown.insert_hashed(hash, marker);
+                n_copied += 1;
+            }
+        }
+        grown.add_inserted(n_copied);
+
+        // the old mapping has to go before its file gets replaced
+        drop(std::mem::replace(self, grown));
+        if !in_memory {
+            fs::rename(&tmp_filename, &self.filename)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the murmur3 checksum of the bits of the member.
+    fn checksum(&self) -> u64 {
+        let n_bytes = self.bitvec.get().size().div_ceil(8);
+        Murmur3.hash128(&self.bitvec.get().mmap[..n_bytes], 0).0
+    }
+
+    /// Checks the bits still match the checksum computed by the last `persist_to_disk`.
+    pub fn verify(&self) -> Result<(), ChecksumError> {
+        let expected = self.params.checksum.ok_or(ChecksumError::Missing)?;
+        let found = self.checksum();
+        if found != expected {
+            return Err(ChecksumError::Mismatch { expected, found });
+        }
+        Ok(())
+    }
+
+    /// Returns the header and the bytes of the bit array, to store the member
+    /// somewhere else than its own file (see `BField::save_single`).
+    pub(crate) fn to_parts(&self) -> Result<(Vec<u8>, &[u8]), io::Error> {
+        let n_bytes = self.bitvec.get().size().div_ceil(8);
+        Ok((self.params.to_header()?, &self.bitvec.get().mmap[..n_bytes]))
+    }
+
+    /// Creates an in-memory member of `size` bits from a header and the bytes of the bit
+    /// array read from `reader`, i.e. the parts returned by `to_parts`.
+    pub(crate) fn from_parts<P: AsRef<Path>, R: io::Read>(
+        filename: P,
+        header: &[u8],
+        size: usize,
+        reader: &mut R,
+        read_only: bool,
+        hasher: H,
+    ) -> Result<Self, io::Error> {
+        let bf_params = BFieldParams::<T>::from_header(header)?;
+        bf_params.validate(BFieldError::Corrupt)?;
+        bf_params.validate_size(size, BFieldError::Corrupt)?;
+        bf_params.check_hasher(&hasher)?;
+        let mut bv = MmapBitVec::from_memory(size)?;
+        reader.read_exact(&mut bv.mmap[..size.div_ceil(8)])?;
+
+        Ok(BFieldMember {
+            filename: filename.as_ref().to_path_buf(),
+            bitvec: BitVec::new(bv),
+            rank_table: writable_rank_table(read_only, bf_params.n_marker_bits),
+            max_value: bf_params.max_value(),
+            params: bf_params,
+            hasher,
+            read_only,
+            prefetch_locality: PrefetchLocality::default(),
+            prefetch_distance: PREFETCH_DISTANCE,
+            probe_markers: 0,
+        })
+    }
+
+    /// Returns an in-memory copy of the member, even if it is backed by a file, which
+    /// can be modified without affecting the original.
+    pub fn try_clone(&self) -> Result<Self, io::Error> {
+        let size = self.bitvec.get().size();
+        let n_bytes = size.div_ceil(8);
+        let mut bv = MmapBitVec::from_memory(size)?;
+        bv.mmap[..n_bytes].copy_from_slice(&self.bitvec.get().mmap[..n_bytes]);
+
+        Ok(BFieldMember {
+            filename: self.filename.clone(),
+            bitvec: BitVec::new(bv),
+            params: self.params.clone(),
+            hasher: self.hasher.clone(),
+            read_only: false,
+            prefetch_locality: self.prefetch_locality,
+            prefetch_distance: self.prefetch_distance,
+            probe_markers: self.probe_markers,
+            rank_table: writable_rank_table(false, self.params.n_marker_bits),
+            max_value: self.max_value,
         })
     }
 
+    /// Returns the largest value that can be encoded in a marker of
+    /// `marker_width` bits with `n_marker_bits` set, i.e.
+    /// `C(marker_width, n_marker_bits) - 1`.
+    pub fn max_value(&self) -> u64 {
+        self.max_value
+    }
+
+    /// Checks that `value` can be inserted into this member.
+    #[inline]
+    fn check_insert(&self, value: BFieldVal) -> Result<(), InsertError> {
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
+        let max = self.max_value;
+        if value > max {
+            return Err(InsertError::ValueOutOfRange { value, max });
+        }
+        Ok(())
+    }
+
+    /// Returns the marker of `value`, which `check_insert` needs to have accepted.
+    #[inline]
+    fn marker(&self, value: BFieldVal) -> u128 {
+        self.rank_table
+            .expect("read-only members are rejected by `check_insert`")
+            .rank(value as usize)
+    }
+
+    pub fn try_insert(&self, key: &[u8], value: BFieldVal) -> Result<(), InsertError> {
+        self.check_insert(value)?;
+        self.insert_raw(key, self.marker(value));
+        Ok(())
+    }
+
+    /// Same as `try_insert` for a key already hashed with this member's hasher and seed.
+    pub fn insert_prehashed(&self, hash: (u64, u64), value: BFieldVal) -> Result<(), InsertError> {
+        self.check_insert(value)?;
+        self.insert_hashed(hash, self.marker(value));
+        Ok(())
+    }
+
+    #[cfg(test)]
     pub fn insert(&self, key: &[u8], value: BFieldVal) {
-        // TODO: need to do a check that `value` < allowable range based on
-        // self.params.marker_width and self.params.n_marker_bits
+        self.try_insert(key, value)
+            .expect("Failed to insert into B-field member");
+    }
+
+    /// Same as `try_insert` but returns what `get` would have returned for the key just
+    /// before, hashing it only once, e.g. to notice collisions or values changing.
+    /// Markers are ORed in as usual, so a key that had another value ends up
+    /// indeterminate.
+    pub fn replace(&self, key: &[u8], value: BFieldVal) -> Result<BFieldLookup, InsertError> {
+        self.check_insert(value)?;
         let k = self.params.n_marker_bits;
-        self.insert_raw(key, rank(value as usize, k));
+        let hash = self.hash_key(key);
+        let previous = decode_marker(
+            self.get_hashed(hash, u32::from(k)),
+            u32::from(k),
+            self.max_value,
+        );
+        self.insert_hashed(hash, self.marker(value));
+        Ok(previous)
+    }
+
+    /// Same as `try_insert` but doesn't write anything if the key already has the
+    /// marker of `value`, which saves rewriting bits when rebuilding from mostly
+    /// unchanged keys. Unlike `try_mask_or_insert`, a key with another value isn't
+    /// masked: the new marker is just added on top.
+    ///
+    /// Returns `true` if the marker got written and `false` if it was already present.
+    pub fn insert_if_absent(&self, key: &[u8], value: BFieldVal) -> Result<bool, InsertError> {
+        self.check_insert(value)?;
+        let correct_marker = self.marker(value);
+        let k = u32::from(self.params.n_marker_bits);
+        // hash once for both the lookup and the write
+        let hash = self.hash_key(key);
+        // same check as the `AlreadyPresent` case of `try_mask_or_insert`
+        if self.get_hashed(hash, k) == correct_marker {
+            return Ok(false);
+        }
+        self.insert_hashed(hash, correct_marker);
+        Ok(true)
+    }
+
+    /// Same as `try_insert` but writes the marker bits with atomic ORs, so it can be
+    /// called from several threads at once on the same member, even for keys whose
+    /// markers share bytes. It must still not run concurrently with `try_insert`,
+    /// `insert_many`, lookups or anything taking `&mut self`.
+    pub fn try_insert_atomic(&self, key: &[u8], value: BFieldVal) -> Result<(), InsertError> {
+        self.check_insert(value)?;
+        let hash = self.hash_key(key);
+        self.insert_hashed_atomic(hash, self.marker(value));
+        Ok(())
+    }
+
+    /// Inserts all the given key/value pairs, validating every value before
+    /// writing any of them.
+    ///
+    /// All the keys are hashed up front, which lets us prefetch the marker
+    /// positions of upcoming keys while writing the current one, so this is
+    /// faster than calling `try_insert` in a loop for large batches (see the
+    /// `bfield insert_many` benchmark).
+    pub fn insert_many(&self, items: &[(&[u8], BFieldVal)]) -> Result<(), InsertError> {
+        for (_, value) in items {
+            self.check_insert(*value)?;
+        }
+        let hashes: Vec<(u64, u64)> = items.iter().map(|(key, _)| self.hash_key(key)).collect();
+        for (ix, ((_, value), hash)) in items.iter().zip(&hashes).enumerate() {
+            if let Some(upcoming) = hashes.get(ix + self.prefetch_distance) {
+                self.prefetch_hashed(*upcoming);
+            }
+            self.insert_hashed(*hash, self.marker(*value));
+        }
+        Ok(())
+    }
+
+    /// Same as `insert_many` but inserts the pairs from several threads at once.
+    #[cfg(feature = "rayon")]
+    pub fn par_insert_many(&self, items: &[(&[u8], BFieldVal)]) -> Result<(), InsertError>
+    where
+        T: Sync,
+        H: Sync,
+    {
+        for (_, value) in items {
+            self.check_insert(*value)?;
+        }
+        items.par_iter().for_each(|(key, value)| {
+            let hash = self.hash_key(key);
+            self.insert_hashed_atomic(hash, self.marker(*value));
+        });
+        Ok(())
+    }
+
+    #[inline]
+    fn insert_hashed_atomic(&self, hash: (u64, u64), marker: u128) {
+        let marker_width = self.params.marker_width as usize;
+        for marker_ix in 0usize..self.params.n_hashes as usize {
+            let pos = self.marker_pos(hash, marker_ix);
+            self.bitvec
+                .set_range_atomic(pos..pos + marker_width, marker);
+        }
     }
 
     #[inline]
     fn insert_raw(&self, key: &[u8], marker: u128) {
+        let hash = self.hash_key(key);
+        self.insert_hashed(hash, marker);
+    }
+
+    #[inline]
+    fn insert_hashed(&self, hash: (u64, u64), marker: u128) {
         let marker_width = self.params.marker_width as usize;
-        let hash = murmurhash3_x64_128(key, 0);
+        let mut stack_positions = [0usize; STACK_POSITIONS];
+        let mut heap_positions = Vec::new();
+        let positions = self.marker_positions(
+            hash,
+            0..self.params.n_hashes as usize,
+            &mut stack_positions,
+            &mut heap_positions,
+            prefetch_write,
+        );
+
+        for pos in positions.iter() {
+            self.bitvec
+                .get()
+                .set_range(*pos..*pos + marker_width, marker);
+        }
+    }
+
+    /// Computes the positions of the given markers of a hashed key, prefetching each of
+    /// them. Positions live on the stack for the common case and only spill to the heap
+    /// for unusually high numbers of hashes.
+    #[inline]
+    fn marker_positions<'a>(
+        &self,
+        hash: (u64, u64),
+        markers: std::ops::Range<usize>,
+        stack_positions: &'a mut [usize; STACK_POSITIONS],
+        heap_positions: &'a mut Vec<usize>,
+        prefetch: fn(*const u8, PrefetchLocality),
+    ) -> &'a [usize] {
+        let n_markers = markers.len();
+        let positions: &mut [usize] = if n_markers <= STACK_POSITIONS {
+            &mut stack_positions[..n_markers]
+        } else {
+            heap_positions.resize(n_markers, 0);
+            heap_positions
+        };
+        for (marker_ix, position) in markers.zip(positions.iter_mut()) {
+            let pos = self.marker_pos(hash, marker_ix);
+            *position = pos;
+            unsafe {
+                let ptr: *const u8 = self.bitvec.get().mmap.as_ptr().add(pos >> 3);
+                prefetch(ptr, self.prefetch_locality);
+            }
+        }
+        positions
+    }
 
+    /// Prefetches all the marker positions of a hashed key
+    #[inline]
+    fn prefetch_hashed(&self, hash: (u64, u64)) {
         for marker_ix in 0usize..self.params.n_hashes as usize {
-            let pos = marker_pos(hash, marker_ix, self.bitvec.get().size(), marker_width);
-            self.bitvec.get().set_range(pos..pos + marker_width, marker);
+            let pos = self.marker_pos(hash, marker_ix);
+            unsafe {
+                let ptr: *const u8 = self.bitvec.get().mmap.as_ptr().add(pos >> 3);
+                prefetch_read(ptr, self.prefetch_locality);
+            }
+        }
+    }
+
+    /// Position of the `n`th marker of a hashed key, reduced the way the member was built with.
+    /// With disjoint markers the array is split into `n_hashes` bands and the `n`th marker
+    /// is placed within the `n`th band.
+    #[inline]
+    fn marker_pos(&self, hash: (u64, u64), n: usize) -> usize {
+        let mut size = self.bitvec.get().size();
+        let mut start = 0;
+        if self.params.disjoint {
+            size /= self.params.n_hashes as usize;
+            start = n * size;
         }
+        let marker_width = self.params.marker_width as usize;
+        let hash = match self.params.position_scheme {
+            PositionScheme::DoubleHashing => hash,
+            PositionScheme::MixedDoubleHashing => (hash.0 ^ hash.1, hash.1),
+            PositionScheme::EnhancedDoubleHashing => {
+                let range = (size - marker_width) as u64;
+                // one step of the hash moves a marker by a bit with a modulo but only
+                // by `range / 2^64` bits with `fastrange`
+                let step = if self.params.fastrange {
+                    u64::MAX / range + 1
+                } else {
+                    1
+                };
+                let n = n as u64;
+                let offset = (n * (n + 1) * (n + 2) / 6)
+                    .wrapping_mul(marker_width as u64)
+                    .wrapping_mul(step);
+                (hash.0.wrapping_add(offset), hash.1)
+            }
+        };
+        let pos = if self.params.fastrange {
+            fastrange_marker_pos(hash, n, size, marker_width)
+        } else {
+            marker_pos(hash, n, size, marker_width)
+        };
+        start + pos
     }
 
     /// "Removes" a key from the b-field by flipping an extra bit to make it
@@ -156,71 +1254,227 @@ impl<T: Clone + DeserializeOwned + Serialize> BFieldMember<T> {
     /// Returns `true` if the value was inserted or was already present with
     /// the correct value; `false` if masking occured or if it was already
     /// indeterminate.
+    #[cfg(test)]
     pub fn mask_or_insert(&self, key: &[u8], value: BFieldVal) -> bool {
-        let correct_marker = rank(value as usize, self.params.n_marker_bits);
+        match self
+            .try_mask_or_insert(key, value)
+            .expect("Failed to insert into B-field member")
+        {
+            MaskOutcome::Inserted | MaskOutcome::AlreadyPresent => true,
+            MaskOutcome::Masked | MaskOutcome::Indeterminate => false,
+        }
+    }
+
+    /// Same as `mask_or_insert` but returns what happened to the key, or an error
+    /// if the value is out of range or the member is read-only.
+    #[cfg(test)]
+    pub fn try_mask_or_insert(
+        &self,
+        key: &[u8],
+        value: BFieldVal,
+    ) -> Result<MaskOutcome, InsertError> {
+        self.try_mask_or_insert_hashed(self.hash_key(key), value)
+    }
+
+    /// Same as `try_mask_or_insert` for a key already hashed with `hash`.
+    pub(crate) fn try_mask_or_insert_hashed(
+        &self,
+        hash: (u64, u64),
+        value: BFieldVal,
+    ) -> Result<MaskOutcome, InsertError> {
+        self.check_insert(value)?;
+        let correct_marker = self.marker(value);
         let k = u32::from(self.params.n_marker_bits);
-        let existing_marker = self.get_raw(key, k);
+        let existing_marker = self.get_hashed(hash, k);
 
-        match existing_marker.count_ones().cmp(&k) {
-            Ordering::Greater => false, // already indeterminate
+        let outcome = match existing_marker.count_ones().cmp(&k) {
+            Ordering::Greater => MaskOutcome::Indeterminate,
             Ordering::Equal => {
                 // value already in b-field, but is it correct?
                 if existing_marker == correct_marker {
-                    return true;
-                }
-                // try to find a new, invalid marker that has an extra
-                // bit over the existing marker so that it'll become
-                // indeterminate once we overwrite it
-                let mut pos = 0;
-                let mut new_marker = existing_marker;
-                while new_marker.count_ones() == k {
-                    new_marker = existing_marker | (1 << pos);
-                    pos += 1;
+                    return Ok(MaskOutcome::AlreadyPresent);
                 }
                 // mask out the existing!
-                self.insert_raw(key, new_marker);
-                false
+                self.insert_hashed(hash, mask_marker(existing_marker, k));
+                MaskOutcome::Masked
             }
             Ordering::Less => {
                 // nothing present; insert the value
-                self.insert_raw(key, correct_marker);
-                true
+                self.insert_hashed(hash, correct_marker);
+                MaskOutcome::Inserted
             }
-        }
+        };
+        Ok(outcome)
     }
 
-    #[inline]
-    pub fn get(&self, key: &[u8]) -> BFieldLookup {
+    /// Makes a key hashed with `hash` indeterminate if it resolves to a value, without
+    /// inserting anything else. Returns what the key resolved to before.
+    pub(crate) fn mask_hashed(&self, hash: (u64, u64)) -> Result<BFieldLookup, InsertError> {
+        if self.read_only {
+            return Err(InsertError::ReadOnly);
+        }
         let k = u32::from(self.params.n_marker_bits);
-        let putative_marker = self.get_raw(key, k);
-        match putative_marker.count_ones().cmp(&k) {
-            Ordering::Greater => BFieldLookup::Indeterminate,
-            Ordering::Equal => BFieldLookup::Some(unrank(putative_marker) as u32),
-            Ordering::Less => BFieldLookup::None,
+        let existing_marker = self.get_hashed(hash, k);
+        let lookup = decode_marker(existing_marker, k, self.max_value);
+        if let BFieldLookup::Some(_) = lookup {
+            self.insert_hashed(hash, mask_marker(existing_marker, k));
         }
+        Ok(lookup)
     }
 
+    /// Hashes `key` with the hasher and seed of this member, which is what every insert
+    /// and lookup starts with.
     #[inline]
-    fn get_raw(&self, key: &[u8], k: u32) -> u128 {
-        assert!(self.params.n_hashes <= 16);
-        let marker_width = self.params.marker_width as usize;
-        let hash = murmurhash3_x64_128(key, 0);
+    pub fn hash_key(&self, key: &[u8]) -> (u64, u64) {
+        self.hasher.hash128(key, self.params.seed)
+    }
+
+    /// Experimental: decodes a key inserted several times with different values into all
+    /// the values whose markers are contained in the union of their markers.
+    ///
+    /// This is exact for a single value, but with more the union also contains the
+    /// markers of values that were never inserted: with `m` bits set, every one of the
+    /// `C(m, n_marker_bits)` combinations is returned, so two values can already give
+    /// back six. Use a small set of values per key and expect far more indeterminate
+    /// lookups for other keys since every extra value sets more bits.
+    pub fn get_multi(&self, key: &[u8]) -> Vec<BFieldVal> {
+        let k = self.params.n_marker_bits as usize;
+        let marker = self.get_raw(key, k as u32);
+        let bits: Vec<u32> = (0..128).filter(|bit| marker >> bit & 1 == 1).collect();
+        let max_value = self.max_value;
+        let mut values = Vec::new();
+        if bits.len() < k {
+            return values;
+        }
+        // indices into `bits` of the current combination, in increasing order
+        let mut ixs: Vec<usize> = (0..k).collect();
+        loop {
+            let subset = ixs.iter().fold(0u128, |subset, &ix| subset | 1 << bits[ix]);
+            match checked_unrank(subset) {
+                Some(value) if value <= max_value => values.push(value),
+                _ => {}
+            }
+            // bump the last index that can still move right and reset the ones after it
+            let mut i = k;
+            while i > 0 && ixs[i - 1] == bits.len() - k + i - 1 {
+                i -= 1;
+            }
+            if i == 0 {
+                break;
+            }
+            ixs[i - 1] += 1;
+            for j in i..k {
+                ixs[j] = ixs[j - 1] + 1;
+            }
+        }
+        values.sort_unstable();
+        values
+    }
+
+    #[inline]
+    pub fn get(&self, key: &[u8]) -> BFieldLookup {
+        let k = u32::from(self.params.n_marker_bits);
+        decode_marker(self.get_raw(key, k), k, self.max_value)
+    }
+
+    /// Same as `get` for a key already hashed with this member's hasher and seed.
+    #[inline]
+    pub fn get_prehashed(&self, hash: (u64, u64)) -> BFieldLookup {
+        let k = u32::from(self.params.n_marker_bits);
+        decode_marker(self.get_hashed(hash, k), k, self.max_value)
+    }
+
+    /// Looks up all the given keys, hashing them up front and prefetching the
+    /// marker positions of upcoming keys while reading the current one.
+    pub fn get_many(&self, keys: &[&[u8]]) -> Vec<BFieldLookup> {
+        let k = u32::from(self.params.n_marker_bits);
+        let max_value = self.max_value;
+        let hashes: Vec<(u64, u64)> = keys.iter().map(|key| self.hash_key(key)).collect();
+        hashes
+            .iter()
+            .enumerate()
+            .map(|(ix, hash)| {
+                if let Some(upcoming) = hashes.get(ix + self.prefetch_distance) {
+                    self.prefetch_hashed(*upcoming);
+                }
+                decode_marker(self.get_hashed(*hash, k), k, max_value)
+            })
+            .collect()
+    }
+
+    /// Decodes a marker returned by `get_raw_marker` the same way `get` does.
+    #[inline]
+    pub fn decode(&self, marker: u128) -> BFieldLookup {
+        decode_marker(marker, u32::from(self.params.n_marker_bits), self.max_value)
+    }
+
+    /// Returns the marker merged (ANDed) across all the positions of `key` along with its
+    /// number of set bits, to see why a key is indeterminate or how saturated its
+    /// positions are. Unlike `get`, this doesn't stop early once too few bits are left.
+    pub fn get_raw_marker(&self, key: &[u8]) -> (u128, u32) {
+        let marker = self.get_raw(key, 0);
+        (marker, marker.count_ones())
+    }
+
+    #[inline]
+    fn get_raw(&self, key: &[u8], k: u32) -> u128 {
+        let hash = self.hash_key(key);
+        self.get_hashed(hash, k)
+    }
+
+    /// Merges the markers of a hashed key, returning 0 as soon as fewer than `k` bits
+    /// are left. When prefetching is available, the positions are computed (and
+    /// prefetched) first so the reads overlap, except for the first `probe_markers`
+    /// ones which are read beforehand so misses can return before prefetching the rest.
+    /// Otherwise each position is computed as it's read so an early return skips the
+    /// remaining ones.
+    #[inline]
+    fn get_hashed(&self, hash: (u64, u64), k: u32) -> u128 {
+        let n_hashes = self.params.n_hashes as usize;
+        if !PREFETCHES {
+            return self.merge_markers((0..n_hashes).map(|n| self.marker_pos(hash, n)), k);
+        }
+        let n_probed = usize::min(self.probe_markers, n_hashes);
         let mut merged_marker = u128::MAX;
-        let mut positions: [usize; 16] = [0; 16]; // support up to 16 hashes
-        #[allow(clippy::needless_range_loop)]
-        for marker_ix in 0usize..self.params.n_hashes as usize {
-            let pos = marker_pos(hash, marker_ix, self.bitvec.get().size(), marker_width);
-            positions[marker_ix] = pos;
-            unsafe {
-                let byte_idx_st = pos >> 3;
-                let ptr: *const u8 = self.bitvec.get().mmap.as_ptr().add(byte_idx_st);
-                prefetch_read(ptr);
+        if n_probed > 0 {
+            merged_marker = self.merge_markers((0..n_probed).map(|n| self.marker_pos(hash, n)), k);
+            if merged_marker == 0 {
+                return 0;
             }
         }
+        let mut stack_positions = [0usize; STACK_POSITIONS];
+        let mut heap_positions = Vec::new();
+        let positions = self.marker_positions(
+            hash,
+            n_probed..n_hashes,
+            &mut stack_positions,
+            &mut heap_positions,
+            prefetch_read,
+        );
+        merged_marker &= self.merge_markers(positions.iter().copied(), k);
+        if merged_marker.count_ones() < k {
+            return 0;
+        }
+        merged_marker
+    }
 
-        for pos in positions.iter().take(self.params.n_hashes as usize) {
-            let marker = self.bitvec.get().get_range(*pos..*pos + marker_width);
-            merged_marker &= marker;
+    #[inline]
+    fn merge_markers(&self, positions: impl Iterator<Item = usize>, k: u32) -> u128 {
+        let marker_width = self.params.marker_width as usize;
+        // most markers fit in 64 bits, which saves on 128-bit operations
+        if marker_width <= 64 {
+            let mut merged_marker = u64::MAX;
+            for pos in positions {
+                merged_marker &= self.bitvec.get_range_u64(pos..pos + marker_width);
+                if merged_marker.count_ones() < k {
+                    return 0;
+                }
+            }
+            return u128::from(merged_marker);
+        }
+        let mut merged_marker = u128::MAX;
+        for pos in positions {
+            merged_marker &= self.bitvec.get().get_range(pos..pos + marker_width);
             if merged_marker.count_ones() < k {
                 return 0;
             }
@@ -228,6 +1482,271 @@ impl<T: Clone + DeserializeOwned + Serialize> BFieldMember<T> {
         merged_marker
     }
 
+    /// Unsets every bit of the member so it can be reused without reallocating.
+    pub fn clear(&mut self) -> Result<(), io::Error> {
+        if self.read_only {
+            return Err(io::Error::from(BFieldError::ReadOnly));
+        }
+        let size = self.bitvec.get().size();
+        self.bitvec.get().clear_range(0..size);
+        self.params.n_inserted.store(0, atomic::Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns whether `other` hashes keys to the same positions with the same
+    /// encoding, i.e. whether the two members can be merged.
+    pub fn is_compatible(&self, other: &BFieldMember<T, H>) -> bool {
+        self.info() == other.info()
+            && self.params.seed == other.params.seed
+            && self.params.fastrange == other.params.fastrange
+            && self.params.disjoint == other.params.disjoint
+            && self.params.position_scheme == other.params.position_scheme
+            && self.params.hasher == other.params.hasher
+    }
+
+    /// Sets every bit set in `other` in this member (a bitwise OR), so that this member
+    /// contains the keys of both.
+    pub fn merge(&mut self, other: &BFieldMember<T, H>) -> Result<(), MergeError> {
+        if self.read_only {
+            return Err(MergeError::ReadOnly);
+        }
+        if !self.is_compatible(other) {
+            return Err(MergeError::IncompatibleParams);
+        }
+        let size = self.bitvec.get().size();
+        for start in (0..size).step_by(64) {
+            let end = usize::min(start + 64, size);
+            let bits = other.bitvec.get().get_range(start..end);
+            self.bitvec.get().set_range(start..end, bits);
+        }
+        self.add_inserted(other.n_inserted());
+        Ok(())
+    }
+
+    /// Returns the number of inserts counted with `add_inserted`.
+    pub fn n_inserted(&self) -> u64 {
+        self.params.n_inserted.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Counts `n` more inserted keys, which is up to the caller since only the
+    /// primary array keeps track of them.
+    pub(crate) fn add_inserted(&self, n: u64) {
+        self.params
+            .n_inserted
+            .fetch_add(n, atomic::Ordering::Relaxed);
+    }
+
+    /// Returns the fraction of bits set in this member, from 0 (empty) to 1 (saturated).
+    pub fn fill_ratio(&self) -> f64 {
+        self.set_bits() as f64 / self.bitvec.get().size() as f64
+    }
+
+    /// Returns the number of bits set in this member.
+    pub(crate) fn set_bits(&self) -> u64 {
+        self.rank(0..self.bitvec.get().size()) as u64
+    }
+
+    /// Returns the number of bits set in `range`.
+    pub fn rank(&self, range: std::ops::Range<usize>) -> usize {
+        self.bitvec.get().rank(range)
+    }
+
+    /// Returns the position of the `n`th (from 0) bit set at or after `start`.
+    pub fn select(&self, n: usize, start: usize) -> Option<usize> {
+        self.bitvec.get().select(n, start)
+    }
+
+    /// Buckets `marker_width`-bit windows of the array by how many bits they have set,
+    /// which shows how saturation is spread: lots of windows with `n_marker_bits` or
+    /// more bits set mean lookups will soon start coming back indeterminate.
+    ///
+    /// With `sample`, only that many windows are read, at pseudo-random positions picked
+    /// the same way as marker positions; otherwise every non-overlapping window is read.
+    pub fn popcount_histogram(&self, sample: Option<usize>) -> [u64; 129] {
+        let mut histogram = [0u64; 129];
+        let marker_width = self.params.marker_width as usize;
+        let bv = self.bitvec.get();
+        let mut count = |pos: usize| {
+            histogram[bv.get_range(pos..pos + marker_width).count_ones() as usize] += 1;
+        };
+        match sample {
+            Some(n) => {
+                for i in 0..n as u64 {
+                    let hash = self.hash_key(&i.to_le_bytes());
+                    count(self.marker_pos(hash, 0));
+                }
+            }
+            None => {
+                for pos in (0..=bv.size() - marker_width).step_by(marker_width) {
+                    count(pos);
+                }
+            }
+        }
+        histogram
+    }
+
+    /// Walks every `marker_width`-bit window of the array, one bit apart, and yields the
+    /// position and decoded value of the ones with exactly `n_marker_bits` bits set.
+    ///
+    /// Keys can't be recovered from this since hashes are one-way, but it shows which
+    /// values are encoded where, e.g. to analyze corrupted arrays. Expect lots of false
+    /// markers: windows straddling two overlapping markers, or made of the bits of
+    /// several keys, look just as valid as real ones. This reads the whole array.
+    pub fn iter_markers(&self) -> impl Iterator<Item = (usize, BFieldVal)> + '_ {
+        let marker_width = self.params.marker_width as usize;
+        let k = u32::from(self.params.n_marker_bits);
+        let max_value = self.max_value;
+        let bv = self.bitvec.get();
+        (0..=bv.size() - marker_width).filter_map(move |pos| {
+            match decode_marker(bv.get_range(pos..pos + marker_width), k, max_value) {
+                BFieldLookup::Some(value) => Some((pos, value)),
+                _ => None,
+            }
+        })
+    }
+
+    /// Checks that `rank` and `unrank` are exact inverses for a sample of the values
+    /// that can be inserted with this member's `marker_width` and `n_marker_bits`, so a
+    /// broken encoding is caught before inserting anything rather than decoding to the
+    /// wrong values later. Returns an `InvalidData` error naming the first bad value.
+    pub fn check_encoding(&self) -> Result<(), io::Error> {
+        let marker_width = self.params.marker_width;
+        let k = self.params.n_marker_bits;
+        let error = |message: String| Err(io::Error::from(BFieldError::Corrupt(message)));
+        // `rank` only handles up to 9 bits
+        if k >= 10 {
+            return error(format!(
+                "n_marker_bits is {} but values can only be encoded with fewer than 10",
+                k
+            ));
+        }
+        let max = match checked_choose(u64::from(marker_width), k) {
+            Some(n_values) => n_values - 1,
+            None => {
+                return error(format!(
+                    "{} choose {} values don't fit in a u64",
+                    marker_width, k
+                ))
+            }
+        };
+        let in_width = |marker: u128| marker_width == 128 || marker >> marker_width == 0;
+        let samples = (0..=ENCODING_SAMPLES)
+            .map(|i| (u128::from(max) * i / ENCODING_SAMPLES) as u64)
+            // both sides of the switch from the lookup tables to computing markers
+            .chain([MARKER_TABLE_SIZE as u64 - 1, MARKER_TABLE_SIZE as u64])
+            .filter(|value| *value <= max);
+        let table = rank_table(k);
+        for value in samples {
+            let marker = table.rank(value as usize);
+            if marker.count_ones() != u32::from(k)
+                || !in_width(marker)
+                || checked_unrank(marker) != Some(value)
+            {
+                return error(format!(
+                    "value {} doesn't round trip through a marker of {} bits with {} set",
+                    value, marker_width, k
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the member was built with the `expected` parameters, returning an
+    /// `InvalidData` error naming the first one that differs otherwise.
+    pub fn check_expected(&self, expected: &ExpectedParams) -> Result<(), io::Error> {
+        let params = &self.params;
+        for (name, found, wanted) in [
+            (
+                "n_hashes",
+                u64::from(params.n_hashes),
+                u64::from(expected.n_hashes),
+            ),
+            (
+                "marker_width",
+                u64::from(params.marker_width),
+                u64::from(expected.marker_width),
+            ),
+            (
+                "n_marker_bits",
+                u64::from(params.n_marker_bits),
+                u64::from(expected.n_marker_bits),
+            ),
+            ("seed", params.seed, expected.seed),
+        ] {
+            if found != wanted {
+                return Err(io::Error::from(BFieldError::Corrupt(format!(
+                    "{:?} has {} {} but {} was expected",
+                    self.filename, name, found, wanted
+                ))));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes taken by the bit array plus everything it's saved with:
+    /// the magic bytes, the length of the params header, the header itself (format version,
+    /// byte order mark and serialized params) and the size of the array.
+    pub fn memory_bytes(&self) -> usize {
+        let header_len = self.params.to_header().map_or(0, |header| header.len());
+        // `MmapBitVec` writes the header length as a u16 and the size in bits as a u64
+        BF_MAGIC.len() + 2 + header_len + 8 + self.bitvec.get().size().div_ceil(8)
+    }
+
+    /// Sets the cache levels marker positions are prefetched into.
+    pub fn set_prefetch_locality(&mut self, locality: PrefetchLocality) {
+        self.prefetch_locality = locality;
+    }
+
+    /// Sets how many keys ahead `insert_many` and `get_many` prefetch marker positions.
+    pub fn set_prefetch_distance(&mut self, distance: usize) {
+        self.prefetch_distance = distance;
+    }
+
+    /// Sets how many markers lookups read before prefetching the remaining ones, 0 (all
+    /// of them prefetched up front) by default.
+    pub fn set_probe_markers(&mut self, probe_markers: usize) {
+        self.probe_markers = probe_markers;
+    }
+
+    /// Tells the OS how the mapping is going to be accessed.
+    #[allow(unused_variables)]
+    pub fn advise(&self, pattern: AccessPattern) -> Result<(), io::Error> {
+        #[cfg(unix)]
+        self.bitvec.get().mmap.advise(pattern.into())?;
+        Ok(())
+    }
+
+    /// Writes the bits changed through the mapping back to the file and waits for the OS
+    /// to have them on disk. Read-only and in-memory members have nothing to write.
+    pub fn flush(&self) -> Result<(), io::Error> {
+        if self.read_only {
+            return Ok(());
+        }
+        self.write_n_inserted()?;
+        self.bitvec.get().mmap.flush()
+    }
+
+    /// Faults the whole mapping in by asking the OS to page it in and then reading a byte
+    /// of every page, so the first lookups don't wait on the disk.
+    pub fn preload(&self) -> Result<(), io::Error> {
+        self.advise(AccessPattern::WillNeed)?;
+        let bytes = &self.bitvec.get().mmap[..self.bitvec.get().size().div_ceil(8)];
+        for ix in (0..bytes.len()).step_by(PAGE_SIZE) {
+            // volatile so the otherwise unused read isn't optimized away
+            unsafe { std::ptr::read_volatile(&bytes[ix]) };
+        }
+        Ok(())
+    }
+
+    pub fn member_info(&self) -> MemberInfo {
+        MemberInfo {
+            size: self.bitvec.get().size(),
+            n_hashes: self.params.n_hashes,
+            marker_width: self.params.marker_width,
+            n_marker_bits: self.params.n_marker_bits,
+        }
+    }
+
     pub fn info(&self) -> (usize, u8, u8, u8) {
         (
             self.bitvec.get().size(),
@@ -238,19 +1757,70 @@ impl<T: Clone + DeserializeOwned + Serialize> BFieldMember<T> {
     }
 }
 
+/// Returns the table writable members use to turn values into markers, or `None` for
+/// read-only members. `validate` already made sure `rank_table` handles `n_marker_bits`.
+fn writable_rank_table(read_only: bool, n_marker_bits: u8) -> Option<&'static RankTable> {
+    if read_only {
+        return None;
+    }
+    Some(rank_table(n_marker_bits))
+}
+
+/// Turns a merged marker into a lookup result depending on how many bits it has set.
+/// A marker that decodes past `max_value` can only come from a corrupted array and is
+/// treated as indeterminate.
+#[inline]
+fn decode_marker(marker: u128, k: u32, max_value: BFieldVal) -> BFieldLookup {
+    match marker.count_ones().cmp(&k) {
+        Ordering::Greater => BFieldLookup::Indeterminate,
+        Ordering::Equal => match checked_unrank(marker) {
+            Some(value) if value <= max_value => BFieldLookup::Some(value),
+            _ => BFieldLookup::Indeterminate,
+        },
+        Ordering::Less => BFieldLookup::None,
+    }
+}
+
+/// Returns a marker with one more bit set than `marker`, which has `k` bits set, so
+/// that writing it over the positions of a key makes the key indeterminate.
+fn mask_marker(marker: u128, k: u32) -> u128 {
+    let mut pos = 0;
+    let mut new_marker = marker;
+    while new_marker.count_ones() == k {
+        new_marker = marker | (1 << pos);
+        pos += 1;
+    }
+    new_marker
+}
+
 #[inline]
 fn marker_pos(hash: (u64, u64), n: usize, total_size: usize, marker_size: usize) -> usize {
     ((hash.0 as usize).wrapping_add(n.wrapping_mul(hash.1 as usize))) % (total_size - marker_size)
 }
 
+/// Same as `marker_pos` but maps the hash onto the range with Lemire's multiply-shift
+/// (https://lemire.me/blog/2016/06/27/a-fast-alternative-to-the-modulo-reduction/),
+/// which avoids a division and the bias towards the start of the range.
+#[inline]
+fn fastrange_marker_pos(
+    hash: (u64, u64),
+    n: usize,
+    total_size: usize,
+    marker_size: usize,
+) -> usize {
+    let h = hash.0.wrapping_add((n as u64).wrapping_mul(hash.1));
+    ((u128::from(h) * (total_size - marker_size) as u128) >> 64) as usize
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::combinatorial::rank;
 
     #[test]
     fn test_bfield() {
         let bfield: BFieldMember<usize> =
-            BFieldMember::create("test", true, 1024, 3, 64, 4, None).unwrap();
+            BFieldMember::create("test", true, 1024, 3, 64, 4, 0, None).unwrap();
         // check that inserting keys adds new entries
         bfield.insert(b"test", 2);
         assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
@@ -267,29 +1837,437 @@ mod tests {
         // comically small bfield with too many (16) hashes
         // and too many bits (8) to cause saturation
         let bfield: BFieldMember<usize> =
-            BFieldMember::create("test", true, 128, 16, 64, 8, None).unwrap();
+            BFieldMember::create("test", true, 128, 16, 64, 8, 0, None).unwrap();
 
         bfield.insert(b"test", 100);
         assert_eq!(bfield.get(b"test"), BFieldLookup::Indeterminate);
     }
 
+    #[test]
+    fn test_bfield_insert_many() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1_000_000, 3, 16, 4, 0, None).unwrap();
+        let keys: Vec<[u8; 8]> = (0..100u64).map(|i| i.to_be_bytes()).collect();
+        let items: Vec<(&[u8], BFieldVal)> =
+            keys.iter().zip(0..).map(|(k, v)| (&k[..], v)).collect();
+        bfield.insert_many(&items).unwrap();
+        for (key, value) in &items {
+            assert_eq!(bfield.get(key), BFieldLookup::Some(*value));
+        }
+
+        // nothing gets inserted if one of the values is out of range
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 16384, 3, 16, 4, 0, None).unwrap();
+        let res = bfield.insert_many(&[(b"test", 2), (b"test2", 1820)]);
+        assert_eq!(
+            res,
+            Err(InsertError::ValueOutOfRange {
+                value: 1820,
+                max: 1819
+            })
+        );
+        assert_eq!(bfield.get(b"test"), BFieldLookup::None);
+    }
+
+    #[test]
+    fn test_bfield_get_many() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 64, 4, 0, None).unwrap();
+        bfield.insert(b"test", 2);
+        bfield.insert(b"test2", 106);
+        assert_eq!(
+            bfield.get_many(&[b"test", b"test3", b"test2"]),
+            vec![
+                BFieldLookup::Some(2),
+                BFieldLookup::None,
+                BFieldLookup::Some(106)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bfield_set_range_atomic() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 64, 4, 0, None).unwrap();
+        let atomic: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 64, 4, 0, None).unwrap();
+        for (start, width, marker) in [(0, 16, 0b1011), (13, 64, u128::MAX >> 70), (900, 39, 7)] {
+            bfield.bitvec.get().set_range(start..start + width, marker);
+            atomic.bitvec.set_range_atomic(start..start + width, marker);
+        }
+        for start in (0..1024).step_by(64) {
+            assert_eq!(
+                bfield.bitvec.get().get_range(start..start + 64),
+                atomic.bitvec.get().get_range(start..start + 64)
+            );
+        }
+    }
+
+    #[test]
+    fn test_bfield_get_range_u64() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1021, 3, 64, 4, 0, None).unwrap();
+        for i in 0..200u64 {
+            bfield.insert(&i.to_be_bytes(), i);
+        }
+        let bv = &bfield.bitvec;
+        for width in [1, 7, 8, 16, 39, 63, 64] {
+            // the last starts don't have 9 bytes left and fall back to `get_range`
+            for start in (0..1021 - width)
+                .step_by(3)
+                .chain(1021 - width - 8..=1021 - width)
+            {
+                assert_eq!(
+                    u128::from(bv.get_range_u64(start..start + width)),
+                    bv.get().get_range(start..start + width),
+                    "start {} width {}",
+                    start,
+                    width
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bfield_many_hashes() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 16384, 20, 16, 4, 0, None).unwrap();
+        bfield.insert(b"test", 2);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+        assert!(bfield.rank(0..16384) <= 20 * 4);
+        assert_eq!(bfield.get(b"test2"), BFieldLookup::None);
+    }
+
+    #[test]
+    fn test_bfield_merge_markers() {
+        // merging as positions are computed must match merging precomputed ones,
+        // including for the early returns and positions spilled to the heap
+        for n_hashes in [3, 20] {
+            let bfield: BFieldMember<usize> =
+                BFieldMember::create("test", true, 16384, n_hashes, 16, 4, 0, None).unwrap();
+            for i in 0..200usize {
+                bfield.insert(&i.to_be_bytes(), i as BFieldVal % 10);
+            }
+            for i in 0..400usize {
+                let hash = Murmur3.hash128(&i.to_be_bytes(), 0);
+                let positions: Vec<usize> = (0..usize::from(n_hashes))
+                    .map(|n| bfield.marker_pos(hash, n))
+                    .collect();
+                for k in [0, 4] {
+                    let streamed = bfield.merge_markers(
+                        (0..usize::from(n_hashes)).map(|n| bfield.marker_pos(hash, n)),
+                        k,
+                    );
+                    assert_eq!(streamed, bfield.merge_markers(positions.iter().copied(), k));
+                    assert_eq!(streamed, bfield.get_hashed(hash, k));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_bfield_grow() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield.0.bfd");
+        let mut bfield: BFieldMember<usize> =
+            BFieldMember::create(&path, false, 10_000, 3, 16, 4, 0, None).unwrap();
+        let keys: Vec<[u8; 8]> = (0..500u64).map(|i| i.to_be_bytes()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            bfield.insert(key, i as BFieldVal % 100);
+        }
+        let before: Vec<BFieldLookup> = keys.iter().map(|key| bfield.get(key)).collect();
+        assert!(before.contains(&BFieldLookup::Indeterminate));
+        let fill_ratio = bfield.fill_ratio();
+
+        assert_eq!(
+            bfield.grow(1_000, &keys).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        bfield.grow(100_000, &keys).unwrap();
+        assert_eq!(bfield.info().0, 100_000);
+        assert!(bfield.fill_ratio() < fill_ratio);
+        assert_eq!(bfield.n_inserted(), 500);
+        let after: Vec<BFieldLookup> = keys.iter().map(|key| bfield.get(key)).collect();
+        assert_eq!(after, before);
+        assert_eq!(bfield.get(b"absent"), BFieldLookup::None);
+        drop(bfield);
+
+        // the file was swapped for the larger one
+        let bfield: BFieldMember<usize> = BFieldMember::open(&path, true, Murmur3).unwrap();
+        assert_eq!(bfield.info().0, 100_000);
+        let reopened: Vec<BFieldLookup> = keys.iter().map(|key| bfield.get(key)).collect();
+        assert_eq!(reopened, before);
+        assert!(!tmp_dir.path().join("bfield.0.bfd.tmp").exists());
+
+        let mut bfield = bfield;
+        assert_eq!(
+            bfield.grow(200_000, &keys).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    fn test_bfield_flush() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield.0.bfd");
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create(&path, false, 1024, 3, 16, 4, 0, None).unwrap();
+        bfield.insert(b"test", 2);
+        bfield.add_inserted(1);
+        bfield.flush().unwrap();
+
+        // a fresh handle sees the value and the count while the first one is still open
+        let reopened: BFieldMember<usize> = BFieldMember::open(&path, true, Murmur3).unwrap();
+        assert_eq!(reopened.get(b"test"), BFieldLookup::Some(2));
+        assert_eq!(reopened.n_inserted(), 1);
+        assert!(reopened.flush().is_ok());
+
+        let in_memory: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        in_memory.insert(b"test", 2);
+        assert!(in_memory.flush().is_ok());
+    }
+
+    #[test]
+    fn test_bfield_no_hashes() {
+        let res: Result<BFieldMember<usize>, _> =
+            BFieldMember::create("test", true, 1024, 0, 16, 4, 0, None);
+        assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+
     #[test]
     fn test_bfield_bits_set() {
         let bfield: BFieldMember<usize> =
-            BFieldMember::create("test", true, 128, 2, 16, 4, None).unwrap();
+            BFieldMember::create("test", true, 128, 2, 16, 4, 0, None).unwrap();
 
         bfield.insert(b"test", 100);
-        assert_eq!(bfield.bitvec.get().rank(0..128), 8);
+        assert_eq!(bfield.rank(0..128), 8);
+        let first = bfield.select(0, 0).unwrap();
+        assert_eq!(bfield.rank(0..first + 1), 1);
+        assert!(bfield.select(7, first).is_some());
+        assert_eq!(bfield.select(8, 0), None);
         bfield.insert(b"test2", 200);
-        assert_eq!(bfield.bitvec.get().rank(0..128), 16);
+        assert_eq!(bfield.rank(0..128), 16);
         bfield.insert(b"test3", 300);
-        assert!(bfield.bitvec.get().rank(0..128) < 24); // 23 bits set
+        assert!(bfield.rank(0..128) < 24); // 23 bits set
+    }
+
+    #[test]
+    fn test_bfield_max_value() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        // 16 choose 4
+        assert_eq!(bfield.max_value(), 1819);
+
+        bfield.insert(b"test", 1819);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(1819));
+    }
+
+    #[test]
+    #[should_panic(expected = "ValueOutOfRange { value: 1820, max: 1819 }")]
+    fn test_bfield_insert_out_of_range() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        bfield.insert(b"test", 1820);
+    }
+
+    #[test]
+    fn test_bfield_try_insert() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        assert_eq!(bfield.try_insert(b"test", 1819), Ok(()));
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(1819));
+
+        assert_eq!(
+            bfield.try_insert(b"test2", 1820),
+            Err(InsertError::ValueOutOfRange {
+                value: 1820,
+                max: 1819
+            })
+        );
+        assert_eq!(bfield.get(b"test2"), BFieldLookup::None);
+    }
+
+    #[test]
+    fn test_bfield_fill_ratio() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 2, 16, 4, 0, None).unwrap();
+        assert_eq!(bfield.fill_ratio(), 0.);
+
+        let mut previous = 0.;
+        for i in 0..50u64 {
+            bfield.insert(&i.to_be_bytes(), i);
+            let ratio = bfield.fill_ratio();
+            assert!(ratio >= previous);
+            assert!(ratio <= 1.);
+            previous = ratio;
+        }
+        assert!(previous > 0.);
+        assert_eq!(previous, bfield.rank(0..1024) as f64 / 1024.);
+    }
+
+    #[test]
+    fn test_bfield_popcount_histogram() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 2, 16, 4, 0, None).unwrap();
+        let histogram = bfield.popcount_histogram(None);
+        assert_eq!(histogram[0], 64);
+        assert_eq!(histogram.iter().sum::<u64>(), 64);
+
+        for i in 0..50u64 {
+            bfield.insert(&i.to_be_bytes(), i);
+        }
+        let histogram = bfield.popcount_histogram(None);
+        assert_eq!(histogram.iter().sum::<u64>(), 64);
+        assert!(histogram[4..=16].iter().sum::<u64>() > 0);
+        assert_eq!(histogram[17..].iter().sum::<u64>(), 0);
+        let bits_set: u64 = histogram
+            .iter()
+            .enumerate()
+            .map(|(n_bits, n)| n_bits as u64 * n)
+            .sum();
+        assert_eq!(bits_set, bfield.rank(0..1024) as u64);
+
+        let sampled = bfield.popcount_histogram(Some(500));
+        assert_eq!(sampled.iter().sum::<u64>(), 500);
+        assert_eq!(sampled, bfield.popcount_histogram(Some(500)));
+    }
+
+    #[test]
+    fn test_bfield_iter_markers() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 1, 16, 4, 0, None).unwrap();
+        assert_eq!(bfield.iter_markers().count(), 0);
+
+        bfield.insert(b"test", 1_000);
+        let pos = bfield.marker_pos(Murmur3.hash128(b"test", 0), 0);
+        let markers: Vec<(usize, BFieldVal)> = bfield.iter_markers().collect();
+        assert!(markers.contains(&(pos, 1_000)));
+        // shifted windows holding the same 4 bits decode to other values
+        assert!(markers.len() > 1);
+        assert!(markers.iter().all(|(p, _)| *p + 16 > pos && *p < pos + 16));
+    }
+
+    #[test]
+    fn test_bfield_check_expected() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 42, None).unwrap();
+        let expected = ExpectedParams {
+            n_hashes: 3,
+            marker_width: 16,
+            n_marker_bits: 4,
+            seed: 42,
+        };
+        assert!(bfield.check_expected(&expected).is_ok());
+
+        let err = bfield
+            .check_expected(&ExpectedParams {
+                marker_width: 32,
+                ..expected
+            })
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("marker_width 16 but 32"));
+        assert!(bfield
+            .check_expected(&ExpectedParams {
+                seed: 0,
+                ..expected
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_bfield_check_encoding() {
+        for (marker_width, n_marker_bits) in [(16, 4), (64, 1), (64, 9), (128, 3)] {
+            let bfield: BFieldMember<usize> =
+                BFieldMember::create("test", true, 1024, 3, marker_width, n_marker_bits, 0, None)
+                    .unwrap();
+            assert!(bfield.check_encoding().is_ok());
+        }
+
+        // kappas that can't be encoded are rejected up front rather than on the first
+        // insert
+        let err = BFieldMember::<usize>::create("test", true, 1024, 3, 64, 12, 0, None)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("n_marker_bits is 12"));
+    }
+
+    #[test]
+    fn test_bfield_get_multi() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 2, 0, None).unwrap();
+        assert_eq!(bfield.get_multi(b"test"), Vec::<BFieldVal>::new());
+
+        bfield.insert(b"test", 3);
+        assert_eq!(bfield.get_multi(b"test"), vec![3]);
+
+        // two markers sharing no bits decode to all 6 pairs of their 4 bits
+        assert_eq!(rank(3, 2) & rank(100, 2), 0);
+        bfield.insert(b"test", 100);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Indeterminate);
+        let values = bfield.get_multi(b"test");
+        assert_eq!(values.len(), 6);
+        assert!(values.contains(&3) && values.contains(&100));
+    }
+
+    #[test]
+    fn test_bfield_replace() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 2, 16, 4, 0, None).unwrap();
+        assert_eq!(bfield.replace(b"test", 2), Ok(BFieldLookup::None));
+        assert_eq!(bfield.replace(b"test", 2), Ok(BFieldLookup::Some(2)));
+        assert_eq!(bfield.replace(b"test", 3), Ok(BFieldLookup::Some(2)));
+        assert_eq!(bfield.replace(b"test", 3), Ok(BFieldLookup::Indeterminate));
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Indeterminate);
+        assert!(bfield.replace(b"test", 1 << 20).is_err());
+    }
+
+    #[test]
+    fn test_bfield_clear() {
+        let mut bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 64, 4, 0, None).unwrap();
+        bfield.insert(b"test", 2);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+        bfield.clear().unwrap();
+        assert_eq!(bfield.get(b"test"), BFieldLookup::None);
+        assert_eq!(bfield.fill_ratio(), 0.);
+
+        // and it can be reused
+        bfield.insert(b"test", 3);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(3));
+    }
+
+    #[test]
+    fn test_bfield_merge() {
+        let mut bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1000, 3, 64, 4, 0, None).unwrap();
+        let other: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1000, 3, 64, 4, 0, None).unwrap();
+        bfield.insert(b"test", 2);
+        other.insert(b"test2", 106);
+        bfield.merge(&other).unwrap();
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+        assert_eq!(bfield.get(b"test2"), BFieldLookup::Some(106));
+        assert_eq!(bfield.rank(0..1000), 24);
+
+        for other in [
+            BFieldMember::create("test", true, 1024, 3, 64, 4, 0, None).unwrap(),
+            BFieldMember::create("test", true, 1000, 2, 64, 4, 0, None).unwrap(),
+            BFieldMember::create("test", true, 1000, 3, 32, 4, 0, None).unwrap(),
+            BFieldMember::create("test", true, 1000, 3, 64, 3, 0, None).unwrap(),
+            BFieldMember::create("test", true, 1000, 3, 64, 4, 1, None).unwrap(),
+        ] {
+            assert_eq!(bfield.merge(&other), Err(MergeError::IncompatibleParams));
+        }
     }
 
     #[test]
     fn test_bfield_mask_or_insert() {
         let bfield: BFieldMember<usize> =
-            BFieldMember::create("test", true, 1024, 2, 16, 4, None).unwrap();
+            BFieldMember::create("test", true, 1024, 2, 16, 4, 0, None).unwrap();
 
         bfield.insert(b"test", 2);
         assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
@@ -310,4 +2288,495 @@ mod tests {
         assert_eq!(bfield.mask_or_insert(b"test2", 2), true);
         assert_eq!(bfield.get(b"test2"), BFieldLookup::Some(2));
     }
+
+    #[test]
+    fn test_bfield_insert_if_absent() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 2, 16, 4, 0, None).unwrap();
+
+        assert_eq!(bfield.insert_if_absent(b"test", 2), Ok(true));
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+        let bits_set = bfield.rank(0..1024);
+
+        // the same value is already there so nothing gets written
+        assert_eq!(bfield.insert_if_absent(b"test", 2), Ok(false));
+        assert_eq!(bfield.rank(0..1024), bits_set);
+
+        // another value gets added on top of the existing one
+        assert_eq!(bfield.insert_if_absent(b"test", 3), Ok(true));
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Indeterminate);
+
+        assert_eq!(
+            bfield.insert_if_absent(b"test", 1 << 20),
+            Err(InsertError::ValueOutOfRange {
+                value: 1 << 20,
+                max: 1819
+            })
+        );
+    }
+
+    #[test]
+    fn test_bfield_seed() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("seeded.bfd");
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create(&path, false, 1024, 3, 16, 4, 42, None).unwrap();
+        let unseeded: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        bfield.insert(b"test", 2);
+        unseeded.insert(b"test", 2);
+        assert!((0..1024)
+            .step_by(128)
+            .any(|i| bfield.bitvec.get().get_range(i..i + 128)
+                != unseeded.bitvec.get().get_range(i..i + 128)));
+        drop(bfield);
+
+        let bfield: BFieldMember<usize> = BFieldMember::open(&path, true, Murmur3).unwrap();
+        assert_eq!(bfield.params.seed, 42);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+    }
+
+    #[test]
+    fn test_bfield_rank_table() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("rank_table.bfd");
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create(&path, false, 1024, 3, 16, 4, 0, None).unwrap();
+        // the table is fetched up front, so inserts never build it
+        assert!(bfield.rank_table.is_some());
+        bfield.insert(b"test", 2);
+        drop(bfield);
+
+        // read-only members can't insert so they don't hold one
+        let bfield: BFieldMember<usize> = BFieldMember::open(&path, true, Murmur3).unwrap();
+        assert!(bfield.rank_table.is_none());
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+        // but a writable copy of it does
+        assert!(bfield.try_clone().unwrap().rank_table.is_some());
+    }
+
+    #[test]
+    fn test_bfield_open_without_seed() {
+        #[derive(Serialize)]
+        struct OldParams {
+            n_hashes: u8,
+            marker_width: u8,
+            n_marker_bits: u8,
+            other: Option<usize>,
+        }
+        let header = bincode::serialize(&OldParams {
+            n_hashes: 3,
+            marker_width: 16,
+            n_marker_bits: 4,
+            other: Some(7),
+        })
+        .unwrap();
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("old.bfd");
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &header).unwrap();
+
+        let bfield: BFieldMember<usize> = BFieldMember::open(&path, false, Murmur3).unwrap();
+        assert_eq!(bfield.params.seed, 0);
+        assert!(!bfield.params.fastrange);
+        assert_eq!(bfield.params.other, Some(7));
+        assert_eq!(bfield.info(), (1024, 3, 16, 4));
+    }
+
+    #[test]
+    fn test_bfield_open_corrupt_header() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("corrupt.bfd");
+        // valid k/nu/kappa but a garbage `Option` tag after them
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &[3, 16, 4, 0xAB, 0xCD]).unwrap();
+
+        let res: Result<BFieldMember<usize>, _> = BFieldMember::open(&path, true, Murmur3);
+        assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_bfield_header_version() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("future.bfd");
+        let params = BFieldParams {
+            n_hashes: 3,
+            marker_width: 16,
+            n_marker_bits: 4,
+            other: None::<usize>,
+            seed: 0,
+            fastrange: true,
+            secondaries: None,
+            hasher: "murmur3".to_string(),
+            checksum: None,
+            n_inserted: AtomicU64::new(0),
+            hash_check: None,
+            disjoint: false,
+            position_scheme: PositionScheme::DoubleHashing,
+        };
+        let mut header = params.to_header().unwrap();
+        assert_eq!(header[..2], HEADER_VERSION.to_be_bytes());
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &header).unwrap();
+        let bfield: BFieldMember<usize> = BFieldMember::open(&path, true, Murmur3).unwrap();
+        assert_eq!(bfield.info(), (1024, 3, 16, 4));
+        drop(bfield);
+
+        header[..2].copy_from_slice(&(HEADER_VERSION + 1).to_be_bytes());
+        let path = tmp_dir.path().join("future2.bfd");
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &header).unwrap();
+        let res: Result<BFieldMember<usize>, _> = BFieldMember::open(&path, true, Murmur3);
+        let err = res.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version"));
+    }
+
+    #[test]
+    fn test_bfield_byte_order() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 42, Some(7)).unwrap();
+        let header = bfield.params.to_header().unwrap();
+        assert_eq!(header[2..6], [4, 3, 2, 1]);
+
+        // the same params written big-endian by a misconfigured writer
+        let mut swapped = header[..2].to_vec();
+        swapped.extend_from_slice(&BYTE_ORDER_MARK.to_be_bytes());
+        let params = bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .with_big_endian()
+            .serialize(&bfield.params)
+            .unwrap();
+        swapped.extend_from_slice(&params);
+        let path = tmp_dir.path().join("swapped.bfd");
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &swapped).unwrap();
+        let err = BFieldMember::<usize>::open(&path, true, Murmur3)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("big-endian"));
+
+        // and a hash function that changed since the file was built
+        let mut params = bfield.params;
+        params.hash_check = params.hash_check.map(|h| h ^ 1);
+        let path = tmp_dir.path().join("rehashed.bfd");
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &params.to_header().unwrap()).unwrap();
+        let err = BFieldMember::<usize>::open(&path, true, Murmur3)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("same hashes"));
+    }
+
+    #[test]
+    fn test_bfield_preload() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("preload.bfd");
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create(&path, false, 100_000, 3, 16, 4, 0, None).unwrap();
+        bfield.preload().unwrap();
+        bfield.insert(b"test", 2);
+        drop(bfield);
+
+        let bfield: BFieldMember<usize> = BFieldMember::open(&path, true, Murmur3).unwrap();
+        bfield.preload().unwrap();
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+    }
+
+    #[test]
+    fn test_bfield_advise() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield: BFieldMember<usize> = BFieldMember::create(
+            tmp_dir.path().join("advise.bfd"),
+            false,
+            1024,
+            3,
+            64,
+            4,
+            0,
+            None,
+        )
+        .unwrap();
+        bfield.insert(b"test", 2);
+        for pattern in &[
+            AccessPattern::Random,
+            AccessPattern::Sequential,
+            AccessPattern::WillNeed,
+            AccessPattern::Normal,
+        ] {
+            bfield.advise(*pattern).unwrap();
+        }
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(2));
+    }
+
+    #[test]
+    fn test_marker_pos_reductions() {
+        // the legacy reduction has to stay bit-identical for existing files
+        assert_eq!(marker_pos((5, 7), 2, 100, 10), 19);
+        // the sum wraps around
+        assert_eq!(marker_pos((u64::MAX, 3), 1, 1024, 64), 2);
+        assert_eq!(fastrange_marker_pos((0, 0), 0, 1024, 64), 0);
+        assert_eq!(fastrange_marker_pos((u64::MAX, 0), 0, 1024, 64), 959);
+
+        // chi-squared over 100 buckets, which has a mean of 99 for a uniform distribution
+        let range = 1_000_000 - 64;
+        let chi_squared = |reduce: fn((u64, u64), usize, usize, usize) -> usize| {
+            let mut buckets = [0u32; 100];
+            for i in 0..100_000u64 {
+                let hash = Murmur3.hash128(&i.to_be_bytes(), 0);
+                buckets[reduce(hash, 1, 1_000_000, 64) * 100 / range] += 1;
+            }
+            buckets
+                .iter()
+                .map(|&n| (f64::from(n) - 1000.).powi(2) / 1000.)
+                .sum::<f64>()
+        };
+        assert!(chi_squared(fastrange_marker_pos) < 170.);
+        assert!(chi_squared(marker_pos) < 170.);
+    }
+
+    #[test]
+    fn test_position_schemes() {
+        let member = |n_hashes: u8, position_scheme: PositionScheme| {
+            let mut params = BFieldParams::new(n_hashes, 64, 4, 0, None);
+            params.position_scheme = position_scheme;
+            let bfield: BFieldMember<usize> =
+                BFieldMember::create_with_params("test", true, 1_000_000, params, Murmur3).unwrap();
+            bfield
+        };
+        let plain = member(16, PositionScheme::DoubleHashing);
+        let enhanced = member(16, PositionScheme::EnhancedDoubleHashing);
+
+        // both spread positions evenly, see `test_marker_pos_reductions`
+        let range = 1_000_000 - 64;
+        let chi_squared = |bfield: &BFieldMember<usize>| {
+            let mut buckets = [0u32; 100];
+            for i in 0..10_000u64 {
+                let hash = Murmur3.hash128(&i.to_be_bytes(), 0);
+                for n in 0..16 {
+                    buckets[bfield.marker_pos(hash, n) * 100 / range] += 1;
+                }
+            }
+            buckets
+                .iter()
+                .map(|&n| (f64::from(n) - 1600.).powi(2) / 1600.)
+                .sum::<f64>()
+        };
+        assert!(chi_squared(&plain) < 170.);
+        assert!(chi_squared(&enhanced) < 170.);
+
+        // but when `h2` is 0, every plain marker lands on the same position while the
+        // enhanced ones don't even overlap, whichever way positions are reduced
+        let min_gap = |bfield: &BFieldMember<usize>, hash: (u64, u64)| {
+            let mut positions: Vec<usize> = (0..16).map(|n| bfield.marker_pos(hash, n)).collect();
+            positions.sort_unstable();
+            positions.windows(2).map(|w| w[1] - w[0]).min().unwrap()
+        };
+        let mut modulo = member(16, PositionScheme::EnhancedDoubleHashing);
+        modulo.params.fastrange = false;
+        for h1 in [0, 123_456_789, 1 << 40, u64::MAX / 3] {
+            let hash = (h1, 0);
+            assert_eq!(min_gap(&plain, hash), 0);
+            assert!(min_gap(&enhanced, hash) >= 64);
+            assert!(min_gap(&modulo, hash) >= 64);
+            assert_eq!(enhanced.marker_pos(hash, 0), plain.marker_pos(hash, 0));
+        }
+
+        enhanced.insert(b"test", 5);
+        assert_eq!(enhanced.get(b"test"), BFieldLookup::Some(5));
+        assert!(!plain.is_compatible(&enhanced));
+    }
+
+    #[test]
+    fn test_mixed_position_scheme() {
+        let member = |position_scheme: PositionScheme| {
+            let mut params = BFieldParams::new(1, 64, 4, 0, None);
+            params.position_scheme = position_scheme;
+            let bfield: BFieldMember<usize> =
+                BFieldMember::create_with_params("test", true, 1_000_000, params, Murmur3).unwrap();
+            bfield
+        };
+        let plain = member(PositionScheme::DoubleHashing);
+        let mixed = member(PositionScheme::MixedDoubleHashing);
+
+        // keys whose hashes only differ in `h2` all get the same single position
+        // unless it's mixed in
+        let range = 1_000_000 - 64;
+        let buckets = |bfield: &BFieldMember<usize>| {
+            let mut buckets = [0u32; 100];
+            for i in 0..100_000u64 {
+                let h2 = Murmur3.hash128(&i.to_be_bytes(), 0).1;
+                buckets[bfield.marker_pos((123_456_789, h2), 0) * 100 / range] += 1;
+            }
+            buckets
+        };
+        assert_eq!(buckets(&plain).iter().filter(|&&n| n > 0).count(), 1);
+        let chi_squared: f64 = buckets(&mixed)
+            .iter()
+            .map(|&n| (f64::from(n) - 1000.).powi(2) / 1000.)
+            .sum();
+        assert!(chi_squared < 170.);
+
+        // and it spreads keys hashed as usual just as well
+        let mut buckets = [0u32; 100];
+        for i in 0..100_000u64 {
+            let hash = Murmur3.hash128(&i.to_be_bytes(), 0);
+            buckets[mixed.marker_pos(hash, 0) * 100 / range] += 1;
+        }
+        let chi_squared: f64 = buckets
+            .iter()
+            .map(|&n| (f64::from(n) - 1000.).powi(2) / 1000.)
+            .sum();
+        assert!(chi_squared < 170.);
+
+        mixed.insert(b"test", 5);
+        assert_eq!(mixed.get(b"test"), BFieldLookup::Some(5));
+        assert!(!plain.is_compatible(&mixed));
+    }
+
+    #[test]
+    fn test_disjoint_marker_pos() {
+        for &(size, n_hashes, marker_width) in &[(100, 3, 16), (67, 2, 32), (1024, 7, 64)] {
+            let mut params = BFieldParams::new(n_hashes, marker_width, 4, 0, None);
+            params.disjoint = true;
+            let bfield: BFieldMember<usize> =
+                BFieldMember::create_with_params("test", true, size, params, Murmur3).unwrap();
+            let band = size / n_hashes as usize;
+            let marker_width = marker_width as usize;
+            for i in 0..1_000u64 {
+                let hash = Murmur3.hash128(&i.to_be_bytes(), 0);
+                let mut positions: Vec<usize> = (0..n_hashes as usize)
+                    .map(|n| bfield.marker_pos(hash, n))
+                    .collect();
+                for (n, pos) in positions.iter().enumerate() {
+                    assert!(*pos >= n * band && *pos + marker_width <= (n + 1) * band);
+                }
+                positions.sort_unstable();
+                assert!(positions.windows(2).all(|w| w[0] + marker_width <= w[1]));
+            }
+        }
+
+        // every band needs to fit a marker
+        let mut params = BFieldParams::<usize>::new(3, 32, 4, 0, None);
+        params.disjoint = true;
+        assert!(BFieldMember::create_with_params("test", true, 96, params, Murmur3).is_err());
+
+        let mut params = BFieldParams::new(3, 16, 4, 0, None);
+        params.disjoint = true;
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create_with_params("test", true, 100, params, Murmur3).unwrap();
+        bfield.insert(b"test", 5);
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(5));
+    }
+
+    #[test]
+    fn test_bfield_invalid_params() {
+        for &(size, n_hashes, marker_width, n_marker_bits) in &[
+            (1024, 3, 200, 4),
+            (1024, 3, 4, 8),
+            (1024, 3, 64, 0),
+            (1024, 3, 64, 10),
+            (32, 3, 64, 4),
+        ] {
+            let res: Result<BFieldMember<usize>, _> = BFieldMember::create(
+                "test",
+                true,
+                size,
+                n_hashes,
+                marker_width,
+                n_marker_bits,
+                0,
+                None,
+            );
+            let err = res.err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert!(matches!(
+                BFieldError::from(err),
+                BFieldError::InvalidParams(_)
+            ));
+        }
+
+        // a file whose markers don't fit can't be opened either
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("tiny.bfd");
+        let header = BFieldParams::<usize>::new(3, 64, 4, 0, None)
+            .to_header()
+            .unwrap();
+        MmapBitVec::create(&path, 64, Some(BF_MAGIC), &header).unwrap();
+        let res: Result<BFieldMember<usize>, _> = BFieldMember::open(&path, true, Murmur3);
+        let err = res.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(BFieldError::from(err), BFieldError::Corrupt(_)));
+
+        // and neither can one whose params are cut short
+        let path = tmp_dir.path().join("truncated.bfd");
+        MmapBitVec::create(&path, 1024, Some(BF_MAGIC), &header[..header.len() / 2]).unwrap();
+        let res: Result<BFieldMember<usize>, _> = BFieldMember::open(&path, true, Murmur3);
+        assert!(matches!(
+            BFieldError::from(res.err().unwrap()),
+            BFieldError::Deserialize(_)
+        ));
+    }
+
+    #[test]
+    fn test_bfield_memory_bytes() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("member.bfd");
+        let bfield: BFieldMember<String> =
+            BFieldMember::create(&path, false, 1001, 3, 64, 4, 0, Some("extra".into())).unwrap();
+        let file_len = fs::metadata(&path).unwrap().len();
+        assert_eq!(bfield.memory_bytes() as u64, file_len);
+
+        let in_memory: BFieldMember<String> =
+            BFieldMember::create("test", true, 1001, 3, 64, 4, 0, Some("extra".into())).unwrap();
+        assert_eq!(in_memory.memory_bytes() as u64, file_len);
+    }
+
+    #[test]
+    fn test_bfield_get_raw_marker() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        assert_eq!(bfield.get_raw_marker(b"test"), (0, 0));
+
+        bfield.insert(b"test", 2);
+        assert_eq!(bfield.get_raw_marker(b"test"), (rank(2, 4), 4));
+    }
+
+    #[test]
+    fn test_bfield_marker_layout() {
+        // files are only portable if markers keep the same bit order in the array: the
+        // most significant bit of the marker first, bits filling each byte from its
+        // most significant one
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 1, 16, 4, 0, None).unwrap();
+        let value = 1_234;
+        bfield.insert(b"test", value);
+        let pos = bfield.marker_pos(Murmur3.hash128(b"test", 0), 0);
+        let bytes = &bfield.bitvec.get().mmap[..128];
+        let mut marker = 0u128;
+        for bit in pos..pos + 16 {
+            marker = (marker << 1) | u128::from((bytes[bit >> 3] >> (7 - (bit & 7))) & 1);
+        }
+        assert_eq!(marker, rank(value as usize, 4));
+        assert_eq!(checked_unrank(marker), Some(value));
+        assert_eq!(bfield.get(b"test"), BFieldLookup::Some(value));
+    }
+
+    #[test]
+    fn test_decode_corrupted_marker() {
+        let bfield: BFieldMember<usize> =
+            BFieldMember::create("test", true, 1024, 3, 16, 4, 0, None).unwrap();
+        let max_value = bfield.max_value();
+        assert_eq!(
+            decode_marker(rank(7, 4), 4, max_value),
+            BFieldLookup::Some(7)
+        );
+        // 4 bits set but past the 16 bits of the marker, so past the largest value
+        assert_eq!(
+            decode_marker(0b111 | 1 << 100, 4, max_value),
+            BFieldLookup::Indeterminate
+        );
+        // enough bits set for the value to overflow, which shouldn't panic
+        assert_eq!(
+            decode_marker(u128::MAX << 64, 64, u64::MAX),
+            BFieldLookup::Indeterminate
+        );
+    }
 }
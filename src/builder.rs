@@ -0,0 +1,333 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::bfield::BField;
+use crate::bfield_member::PositionScheme;
+use crate::error::BFieldError;
+use crate::hasher::Murmur3;
+
+/// A builder for `BField`s, as an alternative to the long list of positional
+/// arguments of `BField::create`.
+///
+/// Every parameter but the primary array `size` has a default:
+/// `n_hashes` = 3, `marker_width` = 64, `n_marker_bits` = 4, `secondary_scaledown` = 0.1,
+/// `max_scaledown` = 0.025, `n_secondaries` = 4, created on disk with a seed of 0 and
+/// markers placed anywhere in the arrays with plain double hashing.
+///
+/// ```no_run
+/// use bfield::BFieldBuilder;
+///
+/// let bfield = BFieldBuilder::<String>::new(1_000_000)
+///     .n_hashes(3)
+///     .marker_width(39)
+///     .n_marker_bits(4)
+///     .build("/tmp/bfield")
+///     .expect("Failed to build B-field");
+/// ```
+#[derive(Debug, Clone)]
+pub struct BFieldBuilder<T> {
+    size: usize,
+    n_hashes: u8,
+    marker_width: u8,
+    n_marker_bits: u8,
+    secondary_scaledown: f64,
+    max_scaledown: f64,
+    n_secondaries: u8,
+    in_memory: bool,
+    seed: u64,
+    disjoint_markers: bool,
+    position_scheme: PositionScheme,
+    other_params: T,
+}
+
+impl<T: Clone + Default + DeserializeOwned + Serialize> BFieldBuilder<T> {
+    /// Starts a builder for a `BField` whose primary array has `size` bits.
+    pub fn new(size: usize) -> Self {
+        Self::with_other_params(size, T::default())
+    }
+}
+
+impl<T: Clone + DeserializeOwned + Serialize> BFieldBuilder<T> {
+    /// Same as `new` for extra params without a default.
+    pub fn with_other_params(size: usize, other_params: T) -> Self {
+        BFieldBuilder {
+            size,
+            n_hashes: 3,
+            marker_width: 64,
+            n_marker_bits: 4,
+            secondary_scaledown: 0.1,
+            max_scaledown: 0.025,
+            n_secondaries: 4,
+            in_memory: false,
+            seed: 0,
+            disjoint_markers: false,
+            position_scheme: PositionScheme::DoubleHashing,
+            other_params,
+        }
+    }
+
+    /// The number of hash functions _k_ to use.
+    pub fn n_hashes(mut self, n_hashes: u8) -> Self {
+        self.n_hashes = n_hashes;
+        self
+    }
+
+    /// The length ν (nu) of each marker bit-string, at most 128.
+    pub fn marker_width(mut self, marker_width: u8) -> Self {
+        self.marker_width = marker_width;
+        self
+    }
+
+    /// Same as `marker_width` for a width known at compile time, which rejects widths
+    /// that can't fit in the `u128` markers are read into when compiling rather than
+    /// when building.
+    ///
+    /// ```
+    /// use bfield::BFieldBuilder;
+    ///
+    /// let builder = BFieldBuilder::<String>::new(1_000_000).fixed_marker_width::<39>();
+    /// ```
+    ///
+    /// ```compile_fail
+    /// use bfield::BFieldBuilder;
+    ///
+    /// let builder = BFieldBuilder::<String>::new(1_000_000).fixed_marker_width::<200>();
+    /// ```
+    pub fn fixed_marker_width<const W: u8>(self) -> Self {
+        let () = MarkerWidth::<W>::VALID;
+        self.marker_width(W)
+    }
+
+    /// The number κ (kappa) of 1s in each marker, at most `marker_width`.
+    pub fn n_marker_bits(mut self, n_marker_bits: u8) -> Self {
+        self.n_marker_bits = n_marker_bits;
+        self
+    }
+
+    /// The scaling factor β (beta) of each secondary array size, in `(0, 1]`.
+    pub fn secondary_scaledown(mut self, secondary_scaledown: f64) -> Self {
+        self.secondary_scaledown = secondary_scaledown;
+        self
+    }
+
+    /// The smallest a secondary array can get relative to the primary one, in `(0, 1]`.
+    pub fn max_scaledown(mut self, max_scaledown: f64) -> Self {
+        self.max_scaledown = max_scaledown;
+        self
+    }
+
+    /// The number of arrays, including the primary one.
+    pub fn n_secondaries(mut self, n_secondaries: u8) -> Self {
+        self.n_secondaries = n_secondaries;
+        self
+    }
+
+    /// Whether to build the `BField` in memory; call `persist_to_disk` to save it afterwards.
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.in_memory = in_memory;
+        self
+    }
+
+    /// The seed of the murmur hash, see `BField::create_with_seed`.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Whether to split each array into `n_hashes` bands and put the markers of a key in
+    /// a band each (like a partitioned Bloom filter), so they can't overlap and skew
+    /// lookups when arrays are small. Every band needs to be wider than `marker_width`.
+    pub fn disjoint_markers(mut self, disjoint_markers: bool) -> Self {
+        self.disjoint_markers = disjoint_markers;
+        self
+    }
+
+    /// How marker positions are derived from the hash of each key, see `PositionScheme`.
+    /// It's saved in the headers, so `load` always places markers the same way.
+    pub fn position_scheme(mut self, position_scheme: PositionScheme) -> Self {
+        self.position_scheme = position_scheme;
+        self
+    }
+
+    /// Extra params saved along with the `BField` and returned by `BField::params`.
+    pub fn other_params(mut self, other_params: T) -> Self {
+        self.other_params = other_params;
+        self
+    }
+
+    /// Creates the `BField` (which checks the parameters first), with the arrays named after the last
+    /// component of `path` and placed in its parent directory, e.g. `/tmp/bfield` creates
+    /// `/tmp/bfield.0.bfd`, `/tmp/bfield.1.bfd`, etc.
+    pub fn build<P: AsRef<Path>>(&self, path: P) -> Result<BField<T>, io::Error> {
+        let path = path.as_ref();
+        let filename = match path.file_name() {
+            Some(f) => f.to_string_lossy(),
+            None => {
+                return Err(io::Error::from(BFieldError::InvalidParams(format!(
+                    "Couldn't get filename from {:?}",
+                    path
+                ))));
+            }
+        };
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        self.build_with_naming(|n| directory.join(format!("{filename}.{n}.bfd")))
+    }
+
+    /// Creates the `BField` with the path of each array file given by `naming`, called
+    /// with the array index, see `BField::create_with_naming`.
+    pub fn build_with_naming<F>(&self, naming: F) -> Result<BField<T>, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        BField::create_with_placement(
+            naming,
+            self.size,
+            self.n_hashes,
+            self.marker_width,
+            self.n_marker_bits,
+            self.secondary_scaledown,
+            self.max_scaledown,
+            self.n_secondaries,
+            self.in_memory,
+            self.seed,
+            self.disjoint_markers,
+            self.position_scheme,
+            Murmur3,
+            self.other_params.clone(),
+        )
+    }
+}
+
+/// Checks a `marker_width` given as a const generic when it gets monomorphized.
+struct MarkerWidth<const W: u8>;
+
+impl<const W: u8> MarkerWidth<W> {
+    const VALID: () = assert!(W > 0 && W <= 128, "marker_width needs to be in 1..=128");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemberInfo, MergeError};
+
+    #[test]
+    fn can_build_with_defaults() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield: BField<String> = BFieldBuilder::new(1_000_000)
+            .marker_width(39)
+            .other_params("extra".to_string())
+            .build(tmp_dir.path().join("bfield"))
+            .expect("to build");
+        let info = bfield.member_info();
+        assert_eq!(info.len(), 4);
+        assert_eq!(
+            info[0],
+            MemberInfo {
+                size: 1_000_000,
+                n_hashes: 3,
+                marker_width: 39,
+                n_marker_bits: 4,
+            }
+        );
+        assert_eq!(bfield.params(), &Some("extra".to_string()));
+        assert!(tmp_dir.path().join("bfield.3.bfd").exists());
+
+        let fixed: BField<String> = BFieldBuilder::new(1_000_000)
+            .fixed_marker_width::<39>()
+            .in_memory(true)
+            .build(tmp_dir.path().join("fixed"))
+            .expect("to build");
+        assert_eq!(fixed.member_info()[0], info[0]);
+
+        bfield.insert(b"test", 42, 0);
+        assert_eq!(bfield.get(b"test"), Some(42));
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield");
+        let builder = BFieldBuilder::<String>::new(100_000).in_memory(true);
+        for invalid in &[
+            builder.clone().n_hashes(0),
+            builder.clone().marker_width(200),
+            builder.clone().marker_width(4).n_marker_bits(8),
+            builder.clone().secondary_scaledown(0.),
+            builder.clone().max_scaledown(1.5),
+            builder.clone().n_secondaries(0),
+        ] {
+            let err = invalid.build(&path).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+        assert!(builder.build(&path).is_ok());
+
+        // the 300-bit secondary array only leaves 100 bits to each of the 128-bit markers
+        let err = BFieldBuilder::<String>::new(100_000)
+            .in_memory(true)
+            .marker_width(128)
+            .n_secondaries(2)
+            .max_scaledown(0.001)
+            .secondary_scaledown(0.003)
+            .disjoint_markers(true)
+            .build(&path)
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn can_build_with_enhanced_double_hashing() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield");
+        let bfield = BFieldBuilder::<String>::new(1_000_000)
+            .n_hashes(10)
+            .n_secondaries(2)
+            .position_scheme(PositionScheme::EnhancedDoubleHashing)
+            .build(&path)
+            .expect("to build");
+        for pass in 0..2 {
+            for i in 0..1_000u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        drop(bfield);
+
+        let bfield: BField<String> = BField::load(path.with_extension("0.bfd"), true).unwrap();
+        for i in 0..1_000u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        let other = BFieldBuilder::<String>::new(1_000_000)
+            .n_hashes(10)
+            .n_secondaries(2)
+            .in_memory(true)
+            .build(tmp_dir.path().join("other"))
+            .unwrap();
+        let mut bfield = bfield.try_clone().unwrap();
+        assert_eq!(bfield.merge(&other), Err(MergeError::IncompatibleParams));
+    }
+
+    #[test]
+    fn can_build_with_disjoint_markers() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("bfield");
+        let bfield: BField<String> = BFieldBuilder::new(1_000_000)
+            .n_secondaries(2)
+            .disjoint_markers(true)
+            .build(&path)
+            .expect("to build");
+        for pass in 0..2 {
+            for i in 0..1_000u64 {
+                bfield.insert(i.to_be_bytes(), i, pass);
+            }
+        }
+        drop(bfield);
+
+        let bfield = BField::<String>::load(tmp_dir.path().join("bfield.0.bfd"), true).unwrap();
+        for i in 0..1_000u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+    }
+}
@@ -1,54 +1,98 @@
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
+use once_cell::sync::OnceCell;
 use std::convert::TryFrom;
 
-const MARKER_TABLE_SIZE: usize = 200_000;
-
-// TODO: replace with const fn when it is possible
-// (for and if are not allowed in const fn on current stable)
-// https://github.com/rust-lang/rust/issues/87575
-static MARKER_TABLES: Lazy<HashMap<u8, Vec<u128>>> = Lazy::new(|| {
-    let mut m = HashMap::new();
-    for k in 1..10u8 {
-        let mut table = vec![0u128; MARKER_TABLE_SIZE];
-        let table_size = if k == 1 {
-            128
-        } else if k == 2 {
-            8128
-        } else {
-            table.len()
-        };
-
-        table[0] = ((1 << k) - 1) as u128;
-        for i in 1..table_size {
-            table[i] = next_rank(table[i - 1]);
+pub(crate) const MARKER_TABLE_SIZE: usize = 200_000;
+
+/// The markers of the first values for one `k` (`n_marker_bits`), in order, so `rank`
+/// is a lookup for them rather than a loop over the bits.
+///
+/// It holds `min(C(128, k), MARKER_TABLE_SIZE)` markers of 16 bytes: 2 KB for
+/// `k == 1`, 127 KB for `k == 2` and 3.2 MB from `k == 3` on.
+pub(crate) struct RankTable {
+    k: u8,
+    markers: Vec<u128>,
+}
+
+impl RankTable {
+    pub(crate) fn new(k: u8) -> Self {
+        let size = checked_choose(128, k).map_or(MARKER_TABLE_SIZE, |n| {
+            n.min(MARKER_TABLE_SIZE as u64) as usize
+        });
+        let mut markers = Vec::with_capacity(size);
+        let mut marker = (1u128 << k) - 1;
+        markers.push(marker);
+        for _ in 1..size {
+            marker = next_rank(marker);
+            markers.push(marker);
         }
-        m.insert(k, table);
+        RankTable { k, markers }
     }
-    m
-});
+
+    /// Returns the marker of `value`, which needs to be encodable with the table's
+    /// `k` bits.
+    #[inline]
+    pub(crate) fn rank(&self, value: usize) -> u128 {
+        // it's possible this may return a bad value if value > (128 choose k)
+        match self.markers.get(value) {
+            Some(marker) => *marker,
+            None => rank_uncached(value, self.k),
+        }
+    }
+}
+
+// `OnceCell::new` is const, so this doesn't need `Lazy` like a `Vec` of tables would
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY_TABLE: OnceCell<RankTable> = OnceCell::new();
+
+/// The tables of every supported `k` (1 to 9), each built on its first use.
+static RANK_TABLES: [OnceCell<RankTable>; 9] = [EMPTY_TABLE; 9];
+
+/// Returns the table for `k`, building it if it's the first time it's asked for.
+/// `BFieldMember` fetches its table once when it's created or loaded, so inserts never
+/// wait on it.
+pub(crate) fn rank_table(k: u8) -> &'static RankTable {
+    assert!(k > 0 && k < 10, "kappa needs to be less than 10");
+    RANK_TABLES[usize::from(k) - 1].get_or_init(|| RankTable::new(k))
+}
+
+/// Builds the lookup table used to turn values into markers of `n_marker_bits` bits,
+/// which otherwise happens when the first writable `BField` using them is created or
+/// loaded (it takes 3.2 MB for 3 bits or more, see `RankTable`).
+///
+/// The tables are shared by the whole process and each one is built only once, so
+/// calling this is never needed for correctness. It's useful before timing the
+/// creation of a `BField`, so the table isn't counted in it.
+pub fn precompute_rank_table(n_marker_bits: u8) {
+    if n_marker_bits > 0 && n_marker_bits < 10 {
+        rank_table(n_marker_bits);
+    }
+}
 
 /// https://en.wikipedia.org/wiki/Combinatorial_number_system
+#[cfg(test)]
 pub fn rank(value: usize, k: u8) -> u128 {
-    assert!(k > 0 && k < 10, "kappa needs to be less than 10");
-    // it's possible this may overflow if value > (128 choose k) or return
-    // a bad value (0) if value > (128 choose k) and k == 1 or 2
-    if value >= MARKER_TABLE_SIZE {
-        let mut marker = MARKER_TABLES[&k][MARKER_TABLE_SIZE - 1];
-        for _ in 0..(value - MARKER_TABLE_SIZE) {
-            // next_rank would overflow if we pass 0, we return it instead
-            if marker == 0 {
-                return marker;
-            }
-            marker = next_rank(marker);
+    rank_table(k).rank(value)
+}
+
+/// Computes the marker of `value` without the lookup tables by greedily picking, from
+/// the highest bit down, the largest position `c` such that `choose(c, i) <= value`.
+fn rank_uncached(value: usize, k: u8) -> u128 {
+    let mut remaining = value as u64;
+    let mut marker = 0u128;
+    for i in (1..=k).rev() {
+        // choose(i - 1, i) == 0 so this is always a valid starting point
+        let mut c = u64::from(i) - 1;
+        while c + 1 < 128 && choose(c + 1, i) <= remaining {
+            c += 1;
         }
-        marker
-    } else {
-        MARKER_TABLES[&k][value]
+        marker |= 1 << c;
+        remaining -= choose(c, i);
     }
+    marker
 }
 
 /// https://en.wikipedia.org/wiki/Combinatorial_number_system
+#[cfg(test)]
 pub fn unrank(marker: u128) -> usize {
     // val = choose(rank(0), 1) + choose(rank(1), 2) + choose(rank(2), 3) + ...
     let mut working_marker = marker;
@@ -63,48 +107,86 @@ pub fn unrank(marker: u128) -> usize {
     value as usize
 }
 
+/// Same as `unrank` but returns `None` instead of overflowing (or panicking) when the
+/// value doesn't fit in a `u64`, which can only happen for markers read from a
+/// corrupted array.
+pub fn checked_unrank(marker: u128) -> Option<u64> {
+    let mut working_marker = marker;
+    let mut value = 0u64;
+    let mut idx = 0;
+    while working_marker != 0 {
+        let rank = u64::from(working_marker.trailing_zeros());
+        working_marker -= 1 << rank;
+        idx += 1;
+        value = value.checked_add(checked_choose(rank, idx)?)?;
+    }
+    Some(value)
+}
+
+/// Returns the largest value that can be inserted into a `BField` built with
+/// `marker_width` and `n_marker_bits`, i.e. `C(marker_width, n_marker_bits) - 1`, to
+/// check a value domain fits before building one.
+///
+/// Panics if `n_marker_bits` is greater than `marker_width` (no value can be encoded)
+/// or if the number of values doesn't fit in a `u64`, see `checked_max_value`.
+pub fn max_value(marker_width: u8, n_marker_bits: u8) -> u64 {
+    assert!(
+        n_marker_bits <= marker_width,
+        "n_marker_bits ({}) can't be greater than marker_width ({})",
+        n_marker_bits,
+        marker_width
+    );
+    choose(u64::from(marker_width), n_marker_bits) - 1
+}
+
+/// Same as `max_value` but returns `None` instead of panicking when `n_marker_bits` is
+/// greater than `marker_width` or when the number of values doesn't fit in a `u64`.
+pub fn checked_max_value(marker_width: u8, n_marker_bits: u8) -> Option<u64> {
+    if n_marker_bits > marker_width {
+        return None;
+    }
+    checked_choose(u64::from(marker_width), n_marker_bits).map(|n_values| n_values - 1)
+}
+
 /// (Hopefully) fast implementation of a binomial.
 ///
-/// This function uses a preset group of equations for k < 8 and then falls back to a
-/// multiplicative implementation that tries to prevent overflows while
-/// maintaining all results as exact integers.
+/// This function uses a preset group of equations for k < 8 and small n and then falls
+/// back to a multiplicative implementation that keeps all results as exact integers.
+///
+/// Panics if the result doesn't fit in a `u64`, see `checked_choose`.
 #[inline]
 pub fn choose(n: u64, k: u8) -> u64 {
-    // (extra border condition for speed-up?)
-    // if n == u64::from(k) {
-    //     return 1;
-    // }
-    match k {
-        0 => 1,
-        1 => n,
-        2 => n * (n - 1) / 2,
-        3 => n * (n - 1) * (n - 2) / 6,
-        4 => n * (n - 1) * (n - 2) * (n - 3) / 24,
-        5 => n * (n - 1) * (n - 2) * (n - 3) * (n - 4) / 120,
-        6 => n * (n - 1) * (n - 2) * (n - 3) * (n - 4) * (n - 5) / 720,
-        7 => n * (n - 1) * (n - 2) * (n - 3) * (n - 4) * (n - 5) * (n - 6) / 5040,
-        _ => {
-            let mut num: u128 = 1;
-            let mut denom: u128 = 1;
-            for i in 1..=u128::from(k) {
-                num *= u128::from(n) + 1 - i;
-                if num % i == 0 {
-                    num /= i;
-                    continue;
-                }
-                denom *= i;
-                if num % denom == 0 {
-                    num /= denom;
-                    denom = 1;
-                }
-            }
-            TryFrom::try_from(num / denom)
-                .unwrap_or_else(|_| panic!("{} choose {} is greater than 2**64", n, k))
-            // (or recursively) choose(n - 1, k - 1) + choose(n-1, k)
-            // for floats, this should work since they handle fractions:
-            // (1..u64::from(k)).map(|i| (n + 1 - i) / i).product(),
+    checked_choose(n, k).unwrap_or_else(|| panic!("{} choose {} is greater than 2**64", n, k))
+}
+
+/// Same as `choose` but returns `None` if the result doesn't fit in a `u64`.
+#[inline]
+pub fn checked_choose(n: u64, k: u8) -> Option<u64> {
+    if u64::from(k) > n {
+        return Some(0);
+    }
+    // these multiply at most 7 factors below 2**9, so they can't overflow a u64
+    if n < 512 {
+        match k {
+            0 => return Some(1),
+            1 => return Some(n),
+            2 => return Some(n * (n - 1) / 2),
+            3 => return Some(n * (n - 1) * (n - 2) / 6),
+            4 => return Some(n * (n - 1) * (n - 2) * (n - 3) / 24),
+            5 => return Some(n * (n - 1) * (n - 2) * (n - 3) * (n - 4) / 120),
+            6 => return Some(n * (n - 1) * (n - 2) * (n - 3) * (n - 4) * (n - 5) / 720),
+            7 => return Some(n * (n - 1) * (n - 2) * (n - 3) * (n - 4) * (n - 5) * (n - 6) / 5040),
+            _ => {}
         }
     }
+    // multiplying by one factor and dividing by the next `i` keeps every step exact:
+    // after `i` steps this is `C(n - k + i, i)`
+    let k = u64::from(k);
+    let mut result: u128 = 1;
+    for i in 1..=k {
+        result = result.checked_mul(u128::from(n - k + i))? / u128::from(i);
+    }
+    u64::try_from(result).ok()
 }
 
 #[inline]
@@ -132,6 +214,51 @@ mod tests {
         assert_eq!(rank(41663, 3).count_ones(), 3);
     }
 
+    #[test]
+    fn test_rank_uncached() {
+        for k in 1..10u8 {
+            let mut marker = rank(MARKER_TABLE_SIZE - 1, k);
+            for value in MARKER_TABLE_SIZE - 1..MARKER_TABLE_SIZE + 100 {
+                if value >= choose(128, k) as usize {
+                    break;
+                }
+                assert_eq!(rank_uncached(value, k), marker);
+                marker = next_rank(marker);
+            }
+        }
+        for value in [0, 1, 23, 41663] {
+            assert_eq!(rank_uncached(value, 3), rank(value, 3));
+        }
+
+        // values past u32::MAX
+        let value = u32::MAX as usize + 12345;
+        assert_eq!(rank(value, 8).count_ones(), 8);
+        assert_eq!(unrank(rank(value, 8)), value);
+    }
+
+    #[test]
+    fn test_precompute_rank_table() {
+        // out of range values are ignored rather than panicking like `rank`
+        precompute_rank_table(0);
+        precompute_rank_table(10);
+        precompute_rank_table(4);
+        assert!(RANK_TABLES[3].get().is_some());
+        assert_eq!(rank(2, 3), 13);
+    }
+
+    #[test]
+    fn test_rank_table() {
+        // small kappas only have as many markers as there are values
+        assert_eq!(RankTable::new(1).markers.len(), 128);
+        assert_eq!(RankTable::new(2).markers.len(), 8128);
+        let table = RankTable::new(4);
+        assert_eq!(table.markers.len(), MARKER_TABLE_SIZE);
+        for value in [0, 1, 35001, MARKER_TABLE_SIZE - 1, MARKER_TABLE_SIZE + 1] {
+            assert_eq!(table.rank(value), rank_uncached(value, 4));
+        }
+        assert_eq!(RankTable::new(1).rank(127), 1 << 127);
+    }
+
     #[test]
     fn test_unrank() {
         // 3 bit markers
@@ -139,6 +266,14 @@ mod tests {
         assert_eq!(unrank(13), 2);
     }
 
+    #[test]
+    fn test_checked_unrank() {
+        assert_eq!(checked_unrank(7), Some(0));
+        assert_eq!(checked_unrank(rank(41663, 3)), Some(41663));
+        // 64 bits set in the upper half of a marker are way past 2**64
+        assert_eq!(checked_unrank(u128::MAX << 64), None);
+    }
+
     #[test]
     fn test_rank_and_unrank() {
         for k in 1..4u8 {
@@ -192,6 +327,46 @@ mod tests {
         assert_eq!(choose(256, 20), 11288510714272000);
     }
 
+    #[test]
+    fn test_max_value() {
+        assert_eq!(max_value(16, 4), 1819);
+        assert_eq!(max_value(39, 4), 82250);
+        assert_eq!(max_value(64, 1), 63);
+        assert_eq!(max_value(64, 0), 0);
+        assert_eq!(max_value(8, 8), 0);
+        // these go through the u128 intermediates of `checked_choose`
+        assert_eq!(max_value(128, 9), 19_062_702_031_999);
+        assert_eq!(max_value(128, 11), 2_433_440_563_030_399);
+        assert_eq!(max_value(128, 13), 211_709_328_983_644_799);
+        assert_eq!(max_value(128, 3), choose(128, 3) - 1);
+    }
+
+    #[test]
+    fn test_checked_max_value() {
+        assert_eq!(checked_max_value(16, 4), Some(1819));
+        assert_eq!(checked_max_value(128, 13), Some(211_709_328_983_644_799));
+        assert_eq!(checked_max_value(4, 5), None);
+        // 128 choose 30 is about 2**96
+        assert_eq!(checked_max_value(128, 30), None);
+    }
+
+    #[test]
+    fn test_checked_choose_overflow() {
+        // these used to overflow the intermediate products rather than return `None`
+        assert_eq!(checked_choose(u64::MAX, 2), None);
+        assert_eq!(checked_choose(128, 64), None);
+        assert_eq!(checked_choose(1 << 40, 9), None);
+        assert_eq!(checked_choose(u64::MAX, 1), Some(u64::MAX));
+        assert_eq!(checked_choose(3, 5), Some(0));
+        assert_eq!(checked_choose(600, 3), Some(35_820_200));
+    }
+
+    #[test]
+    #[should_panic(expected = "n_marker_bits (5) can't be greater than marker_width (4)")]
+    fn test_max_value_too_many_bits() {
+        max_value(4, 5);
+    }
+
     #[test]
     fn test_next_rank() {
         assert_eq!(next_rank(0b1), 0b10);
@@ -0,0 +1,228 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::bfield_member::BFieldVal;
+
+/// The reasons an insertion into a `BField` can be rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertError {
+    /// The value can't be encoded with the configured `marker_width` and
+    /// `n_marker_bits`; `max` is the largest value that can be.
+    ValueOutOfRange {
+        /// The value that was passed in
+        value: BFieldVal,
+        /// The largest encodable value, `C(marker_width, n_marker_bits) - 1`
+        max: u64,
+    },
+    /// The `BField` was opened read-only.
+    ReadOnly,
+    /// The `pass` doesn't match any array of the `BField`.
+    InvalidPass {
+        /// The pass that was passed in
+        pass: usize,
+        /// The number of arrays, so the passes go from 0 to `n_members - 1`
+        n_members: usize,
+    },
+}
+
+impl fmt::Display for InsertError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InsertError::ValueOutOfRange { value, max } => {
+                write!(f, "value {value} is out of range (max {max})")
+            }
+            InsertError::ReadOnly => write!(f, "can't insert into a read-only B-field"),
+            InsertError::InvalidPass { pass, n_members } => write!(
+                f,
+                "pass {pass} is out of range for a B-field with {n_members} arrays"
+            ),
+        }
+    }
+}
+
+impl Error for InsertError {}
+
+/// Returned by `BField::get_checked` when every member of the `BField` was
+/// indeterminate for a key, as opposed to the key being absent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Indeterminate;
+
+impl fmt::Display for Indeterminate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "all B-field members were indeterminate")
+    }
+}
+
+impl Error for Indeterminate {}
+
+/// The reasons merging two `BField`s can fail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeError {
+    /// The `BField`s don't have the same number of members.
+    MemberCountMismatch {
+        /// Number of members of the `BField` being merged into
+        expected: usize,
+        /// Number of members of the other `BField`
+        found: usize,
+    },
+    /// Some members differ in `size`, `n_hashes`, `marker_width`, `n_marker_bits` or `seed`.
+    IncompatibleParams,
+    /// The `BField` being merged into was opened read-only.
+    ReadOnly,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::MemberCountMismatch { expected, found } => write!(
+                f,
+                "can't merge a B-field with {found} members into one with {expected}"
+            ),
+            MergeError::IncompatibleParams => {
+                write!(f, "can't merge B-fields built with different parameters")
+            }
+            MergeError::ReadOnly => write!(f, "can't merge into a read-only B-field"),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+/// The reasons the checksum of a `BField` array can fail to verify.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumError {
+    /// The array was never persisted with a checksum, e.g. it was created by an older
+    /// version or directly on disk without calling `persist_to_disk`.
+    Missing,
+    /// The bits don't match the checksum saved in the header, either because the file
+    /// is corrupted or because it was modified after the checksum was computed.
+    Mismatch {
+        /// The checksum saved in the header
+        expected: u64,
+        /// The checksum of the current bits
+        found: u64,
+    },
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumError::Missing => write!(f, "the B-field array has no checksum"),
+            ChecksumError::Mismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: expected {expected:#018x}, found {found:#018x}"
+            ),
+        }
+    }
+}
+
+impl Error for ChecksumError {}
+
+/// A single error type for all the ways `BField` operations can fail, for applications
+/// that want to handle them with `?` in one place rather than juggle `io::Error` and
+/// the more specific errors of some methods, which all convert into it.
+///
+/// The methods returning an `io::Error` raise the crate's own errors as one of these
+/// variants wrapped in it (with the matching `InvalidInput`, `InvalidData` or
+/// `PermissionDenied` kind), which converting it back unwraps. Anything else, e.g.
+/// errors from the OS, ends up in `Io`.
+#[derive(Debug)]
+pub enum BFieldError {
+    /// Reading, writing or mapping the files failed
+    Io(io::Error),
+    /// The params in the header of an array couldn't be deserialized
+    Deserialize(String),
+    /// The parameters or arguments are invalid or don't match each other
+    InvalidParams(String),
+    /// The value can't be encoded, see `InsertError::ValueOutOfRange`
+    ValueOutOfRange {
+        /// The value that was passed in
+        value: BFieldVal,
+        /// The largest encodable value
+        max: u64,
+    },
+    /// The `BField` was opened read-only
+    ReadOnly,
+    /// The files are corrupted or inconsistent, e.g. a checksum mismatch
+    Corrupt(String),
+}
+
+impl fmt::Display for BFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BFieldError::Io(e) => write!(f, "{e}"),
+            BFieldError::Deserialize(message) => {
+                write!(f, "couldn't deserialize the B-field header: {message}")
+            }
+            BFieldError::InvalidParams(message) => write!(f, "invalid B-field params: {message}"),
+            BFieldError::ValueOutOfRange { value, max } => {
+                write!(f, "value {value} is out of range (max {max})")
+            }
+            BFieldError::ReadOnly => write!(f, "the B-field is read-only"),
+            BFieldError::Corrupt(message) => write!(f, "corrupted B-field: {message}"),
+        }
+    }
+}
+
+impl Error for BFieldError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BFieldError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BFieldError {
+    fn from(e: io::Error) -> Self {
+        if !e.get_ref().is_some_and(|inner| inner.is::<BFieldError>()) {
+            return BFieldError::Io(e);
+        }
+        match e.into_inner().map(|inner| inner.downcast::<BFieldError>()) {
+            Some(Ok(e)) => *e,
+            _ => unreachable!("checked it wraps a `BFieldError` above"),
+        }
+    }
+}
+
+impl From<BFieldError> for io::Error {
+    fn from(e: BFieldError) -> Self {
+        let kind = match e {
+            BFieldError::Io(e) => return e,
+            BFieldError::Deserialize(_) | BFieldError::Corrupt(_) => io::ErrorKind::InvalidData,
+            BFieldError::InvalidParams(_) | BFieldError::ValueOutOfRange { .. } => {
+                io::ErrorKind::InvalidInput
+            }
+            BFieldError::ReadOnly => io::ErrorKind::PermissionDenied,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+impl From<InsertError> for BFieldError {
+    fn from(e: InsertError) -> Self {
+        match e {
+            InsertError::ValueOutOfRange { value, max } => {
+                BFieldError::ValueOutOfRange { value, max }
+            }
+            InsertError::ReadOnly => BFieldError::ReadOnly,
+            InsertError::InvalidPass { .. } => BFieldError::InvalidParams(e.to_string()),
+        }
+    }
+}
+
+impl From<MergeError> for BFieldError {
+    fn from(e: MergeError) -> Self {
+        match e {
+            MergeError::ReadOnly => BFieldError::ReadOnly,
+            _ => BFieldError::InvalidParams(e.to_string()),
+        }
+    }
+}
+
+impl From<ChecksumError> for BFieldError {
+    fn from(e: ChecksumError) -> Self {
+        BFieldError::Corrupt(e.to_string())
+    }
+}
@@ -0,0 +1,47 @@
+use murmurhash3::murmurhash3_x64_128;
+
+/// The hash function used to place keys in a `BField`.
+///
+/// The two 64-bit halves of the hash are combined to get the position of
+/// each of the `n_hashes` markers (double hashing), so both should be well mixed.
+pub trait BFieldHasher: Clone {
+    /// The name saved in the file headers, so that a `BField` can't be opened
+    /// with a different hash function than the one it was built with.
+    fn name(&self) -> &str;
+
+    /// Hashes `key` with the given `seed`.
+    fn hash128(&self, key: &[u8], seed: u64) -> (u64, u64);
+}
+
+/// The x64 128-bit variant of MurmurHash3, used by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Murmur3;
+
+impl BFieldHasher for Murmur3 {
+    fn name(&self) -> &str {
+        "murmur3"
+    }
+
+    #[inline]
+    fn hash128(&self, key: &[u8], seed: u64) -> (u64, u64) {
+        murmurhash3_x64_128(key, seed)
+    }
+}
+
+/// The 128-bit variant of XXH3, which is faster than murmur on most inputs.
+#[cfg(feature = "xxhash-rust")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Xxh3;
+
+#[cfg(feature = "xxhash-rust")]
+impl BFieldHasher for Xxh3 {
+    fn name(&self) -> &str {
+        "xxh3"
+    }
+
+    #[inline]
+    fn hash128(&self, key: &[u8], seed: u64) -> (u64, u64) {
+        let hash = xxhash_rust::xxh3::xxh3_128_with_seed(key, seed);
+        ((hash >> 64) as u64, hash as u64)
+    }
+}
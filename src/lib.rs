@@ -13,9 +13,27 @@
 
 mod bfield;
 mod bfield_member;
+mod builder;
 /// Some combinatorial utilities
 mod combinatorial;
+mod error;
+mod hasher;
+mod read_only;
+/// Helpers to estimate error rates and pick parameters
+mod sizing;
 
-pub use crate::bfield::BField;
-pub use crate::bfield_member::BFieldVal;
-pub use combinatorial::choose;
+pub use crate::bfield::{
+    BField, BuildReport, ForceInsertOutcome, IndeterminateBehavior, MaskStats,
+};
+pub use crate::bfield_member::{
+    AccessPattern, BFieldConfig, BFieldLookup, BFieldVal, ExpectedParams, MemberInfo,
+    NonZeroBFieldVal, PositionScheme, PrefetchLocality, SecondaryParams,
+};
+pub use builder::BFieldBuilder;
+pub use combinatorial::{checked_max_value, choose, max_value, precompute_rank_table};
+pub use error::{BFieldError, ChecksumError, Indeterminate, InsertError, MergeError};
+#[cfg(feature = "xxhash-rust")]
+pub use hasher::Xxh3;
+pub use hasher::{BFieldHasher, Murmur3};
+pub use read_only::{ReadOnly, ReadOnlyBField, Writable};
+pub use sizing::{estimate_indeterminate_rate, optimal_size, plan_layout, Layout};
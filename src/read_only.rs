@@ -0,0 +1,117 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::bfield::BField;
+use crate::hasher::{BFieldHasher, Murmur3};
+
+/// The mode of a `BField` that can be inserted into, which is what `create` and `load`
+/// return.
+#[derive(Debug)]
+pub enum Writable {}
+
+/// The mode of a `ReadOnlyBField`.
+#[derive(Debug)]
+pub enum ReadOnly {}
+
+/// A `BField` that can only be queried.
+///
+/// `BField::load(path, true)` rejects inserts at runtime with `InsertError::ReadOnly`
+/// (or panics, for `insert`), which only shows up once the code runs. A
+/// `ReadOnlyBField` has no inserting, merging or clearing methods at all, so code
+/// handed one can't modify it by mistake:
+///
+/// ```compile_fail
+/// use bfield::ReadOnlyBField;
+///
+/// let bfield: ReadOnlyBField<()> = ReadOnlyBField::open("bfield.bfd").unwrap();
+/// bfield.insert(b"key", 1, 0);
+/// ```
+///
+/// The query methods are the very same as `BField`'s, since they're implemented once
+/// for both modes. Open one with the constructors below, or turn any `BField` into
+/// one with `into_read_only`.
+pub type ReadOnlyBField<T, H = Murmur3> = BField<T, H, ReadOnly>;
+
+// These can't be named `load` like the `BField` ones: `BField::load` would then be
+// ambiguous between the two modes.
+impl<T: Clone + DeserializeOwned + Serialize> ReadOnlyBField<T> {
+    /// Same as `BField::load(main_db_path, true)`.
+    pub fn open<P: AsRef<Path>>(main_db_path: P) -> Result<Self, io::Error> {
+        Ok(BField::load(main_db_path, true)?.into_read_only())
+    }
+
+    /// Same as `BField::load_with_naming(naming, true)`.
+    pub fn open_with_naming<F>(naming: F) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        Ok(BField::load_with_naming(naming, true)?.into_read_only())
+    }
+
+    /// Same as `BField::load_with_paths(paths, true)`.
+    pub fn open_with_paths(paths: &[PathBuf]) -> Result<Self, io::Error> {
+        Ok(BField::load_with_paths(paths, true)?.into_read_only())
+    }
+}
+
+impl<T: Clone + DeserializeOwned + Serialize, H: BFieldHasher> ReadOnlyBField<T, H> {
+    /// Same as `BField::load_with_hasher(naming, true, hasher)`.
+    pub fn open_with_hasher<F>(naming: F, hasher: H) -> Result<Self, io::Error>
+    where
+        F: Fn(usize) -> PathBuf,
+    {
+        Ok(BField::load_with_hasher(naming, true, hasher)?.into_read_only())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_query_read_only() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            100_000,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            1,
+            false,
+            String::new(),
+        )
+        .expect("to build");
+        for i in 0..100u64 {
+            bfield.insert(i.to_be_bytes(), i, 0);
+        }
+        drop(bfield);
+
+        let bfield = ReadOnlyBField::<String>::open(tmp_dir.path().join("bfield.0.bfd")).unwrap();
+        for i in 0..100u64 {
+            assert_eq!(bfield.get(i.to_be_bytes()), Some(i));
+        }
+        assert_eq!(bfield.get(1000u64.to_be_bytes()), None);
+
+        // the inspection methods are there too
+        let (_, _, _, sizes) = bfield.build_params();
+        assert_eq!(sizes.len(), 1);
+        let bits_set = bfield.rank(0, 0..sizes[0]);
+        assert!(bits_set > 0);
+        assert!(bfield.select(0, bits_set - 1, 0).is_some());
+        assert!(bfield.iter_markers(0).count() > 0);
+        assert_eq!(bfield.popcount_histograms(Some(100)).len(), 1);
+
+        // a copy is writable again without touching the files
+        let copy = bfield.try_clone().unwrap();
+        copy.insert(1000u64.to_be_bytes(), 5, 0);
+        assert_eq!(copy.get(1000u64.to_be_bytes()), Some(5));
+        assert_eq!(bfield.get(1000u64.to_be_bytes()), None);
+    }
+}
@@ -0,0 +1,305 @@
+/// Returns the probability that any single bit of a key's merged marker is wrongly set
+/// to 1 (_p_ in the README) after `n_inserted` keys were inserted.
+fn bit_error_rate(size: usize, n_hashes: u8, n_marker_bits: u8, n_inserted: usize) -> f64 {
+    // `size` is the m * kappa bits of the README, each insertion setting
+    // kappa bits for each of the k hashes
+    let k = f64::from(n_hashes);
+    let bits_set = k * n_inserted as f64 * f64::from(n_marker_bits);
+    (1. - (-bits_set / size as f64).exp()).powf(k)
+}
+
+/// Estimates the probability that looking up a key inserted in a `BField` member
+/// returns an indeterminate result (β in the README), given the member parameters
+/// and the number of keys inserted into it.
+///
+/// This uses the same bit-fill model as the Bloom filter false positive rate, except
+/// that each insertion sets `n_marker_bits` bits per hash: a key is indeterminate
+/// if any of the `marker_width - n_marker_bits` bits not set by its own marker ends up
+/// set by other keys in all of its `n_hashes` positions.
+pub fn estimate_indeterminate_rate(
+    size: usize,
+    n_hashes: u8,
+    marker_width: u8,
+    n_marker_bits: u8,
+    n_inserted: usize,
+) -> f64 {
+    let p = bit_error_rate(size, n_hashes, n_marker_bits, n_inserted);
+    1. - (1. - p).powi(i32::from(marker_width) - i32::from(n_marker_bits))
+}
+
+/// Returns the smallest member `size` (in bits) for which `estimate_indeterminate_rate`
+/// predicts at most `target_rate` indeterminate lookups once `n_keys` are inserted.
+///
+/// The result can be passed straight to `BField::create`. Panics if `target_rate` isn't
+/// strictly between 0 and 1 or if `n_hashes` is 0, which leaves every key indeterminate
+/// whatever the size.
+pub fn optimal_size(
+    n_keys: usize,
+    n_hashes: u8,
+    marker_width: u8,
+    n_marker_bits: u8,
+    target_rate: f64,
+) -> usize {
+    assert!(
+        target_rate > 0. && target_rate < 1.,
+        "target_rate needs to be between 0 and 1"
+    );
+    assert!(n_hashes > 0, "n_hashes needs to be at least 1");
+    // members need room for at least one marker
+    let min_size = usize::from(marker_width) + 1;
+    if n_keys == 0 || marker_width <= n_marker_bits {
+        return min_size;
+    }
+    let rate =
+        |size| estimate_indeterminate_rate(size, n_hashes, marker_width, n_marker_bits, n_keys);
+
+    // invert 1 - (1 - p)^(nu - kappa) and then p = (1 - e^(-k * n * kappa / size))^k
+    let max_p = 1. - (1. - target_rate).powf(1. / f64::from(marker_width - n_marker_bits));
+    let max_fill = max_p.powf(1. / f64::from(n_hashes));
+    let bits_set = f64::from(n_hashes) * n_keys as f64 * f64::from(n_marker_bits);
+    let mut size = usize::max((bits_set / -(1. - max_fill).ln()).ceil() as usize, min_size);
+
+    // fix up any floating point error so we get the smallest size meeting the target
+    while rate(size) > target_rate {
+        size += 1;
+    }
+    while size > min_size && rate(size - 1) <= target_rate {
+        size -= 1;
+    }
+    size
+}
+
+/// The arrays picked by `plan_layout` for a `BField`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    /// The size of each array in bits, starting with the primary one
+    pub sizes: Vec<usize>,
+    /// The estimated fraction of keys still indeterminate in every array once all the
+    /// passes are done
+    pub indeterminate_rate: f64,
+}
+
+impl Layout {
+    /// The number of arrays, i.e. the `n_secondaries` of `BField::create`.
+    pub fn n_secondaries(&self) -> u8 {
+        self.sizes.len() as u8
+    }
+}
+
+/// Returns the sizes of the arrays of a `BField` whose primary array has `size` bits:
+/// each one is `secondary_scaledown` times the previous one, but never smaller than
+/// `max_scaledown` times the primary one.
+pub(crate) fn member_sizes(
+    size: usize,
+    secondary_scaledown: f64,
+    max_scaledown: f64,
+    n_secondaries: u8,
+) -> Vec<usize> {
+    let mut sizes = vec![size];
+    for _ in 1..n_secondaries {
+        let prev = sizes[sizes.len() - 1];
+        sizes.push(f64::max(
+            prev as f64 * secondary_scaledown,
+            size as f64 * max_scaledown,
+        ) as usize);
+    }
+    sizes
+}
+
+/// Picks the arrays of a `BField` holding `n_keys` so that at most `target_rate` of them
+/// are still indeterminate after the last pass.
+///
+/// The primary array is sized with `optimal_size` so that `secondary_scaledown` (β) of
+/// the keys are indeterminate in it, then secondary arrays are added following the same
+/// geometry as `BField::create`, each one getting the keys left indeterminate by the
+/// previous ones, until the estimated rate of keys indeterminate in all of them drops
+/// to `target_rate`. Returns `None` if that takes more than 255 arrays. A β of 1 doesn't
+/// shrink anything, so the primary array is then sized for `target_rate` on its own.
+///
+/// Panics if `target_rate` isn't strictly between 0 and 1, if `secondary_scaledown`
+/// isn't in (0, 1] or if `n_hashes` is 0.
+pub fn plan_layout(
+    n_keys: usize,
+    n_hashes: u8,
+    marker_width: u8,
+    n_marker_bits: u8,
+    secondary_scaledown: f64,
+    max_scaledown: f64,
+    target_rate: f64,
+) -> Option<Layout> {
+    assert!(
+        target_rate > 0. && target_rate < 1.,
+        "target_rate needs to be between 0 and 1"
+    );
+    assert!(
+        secondary_scaledown > 0. && secondary_scaledown <= 1.,
+        "secondary_scaledown needs to be in (0, 1]"
+    );
+    let primary_rate = if secondary_scaledown < 1. {
+        secondary_scaledown
+    } else {
+        target_rate
+    };
+    let size = optimal_size(n_keys, n_hashes, marker_width, n_marker_bits, primary_rate);
+    let mut n_left = n_keys as f64;
+    let mut indeterminate_rate = 1.;
+    for n_secondaries in 1..=u8::MAX {
+        let sizes = member_sizes(size, secondary_scaledown, max_scaledown, n_secondaries);
+        let rate = estimate_indeterminate_rate(
+            sizes[sizes.len() - 1],
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            n_left.round() as usize,
+        );
+        n_left *= rate;
+        indeterminate_rate *= rate;
+        if indeterminate_rate <= target_rate {
+            return Some(Layout {
+                sizes,
+                indeterminate_rate,
+            });
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BField;
+
+    #[test]
+    fn test_estimate_indeterminate_rate() {
+        assert_eq!(estimate_indeterminate_rate(1_000_000, 10, 39, 4, 0), 0.);
+
+        let mut previous = 0.;
+        for n in (1_000..20_000).step_by(1_000) {
+            let rate = estimate_indeterminate_rate(1_000_000, 10, 39, 4, n);
+            assert!(rate > previous);
+            assert!(rate < 1.);
+            previous = rate;
+        }
+    }
+
+    #[test]
+    fn test_estimate_matches_indeterminate_rate() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let (size, n_hashes, marker_width, n_marker_bits) = (200_000, 4, 16, 2);
+        let n_inserted = 8_000u32;
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            0.1,
+            0.025,
+            1,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        for i in 0..n_inserted {
+            bfield.insert(i.to_be_bytes(), u64::from(i % 100), 0);
+        }
+        let n_indeterminate = (0..n_inserted)
+            .filter(|i| bfield.get_checked(i.to_be_bytes()).is_err())
+            .count();
+
+        let observed = f64::from(n_indeterminate as u32) / f64::from(n_inserted);
+        let expected = estimate_indeterminate_rate(
+            size,
+            n_hashes,
+            marker_width,
+            n_marker_bits,
+            n_inserted as usize,
+        );
+        assert!(expected > 0.01);
+        assert!(
+            (observed - expected).abs() < expected * 0.25,
+            "observed {}, expected {}",
+            observed,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_optimal_size() {
+        for (n_keys, n_hashes, marker_width, n_marker_bits, target_rate) in [
+            (10_000, 10, 39, 4, 0.1),
+            (1_000_000, 3, 16, 2, 0.01),
+            (123, 1, 64, 8, 0.5),
+        ] {
+            let size = optimal_size(n_keys, n_hashes, marker_width, n_marker_bits, target_rate);
+            let rate = |size| {
+                estimate_indeterminate_rate(size, n_hashes, marker_width, n_marker_bits, n_keys)
+            };
+            assert!(rate(size) <= target_rate);
+            assert!((rate(size) - target_rate).abs() < target_rate * 0.001);
+            assert!(rate(size - 1) > target_rate);
+        }
+
+        // no indeterminacy is possible if every bit of the marker is set
+        assert_eq!(optimal_size(1_000, 3, 4, 4, 0.1), 5);
+    }
+
+    #[test]
+    #[should_panic(expected = "n_hashes needs to be at least 1")]
+    fn test_optimal_size_no_hashes() {
+        optimal_size(1_000, 0, 39, 4, 0.1);
+    }
+
+    #[test]
+    fn test_plan_layout() {
+        let layout = plan_layout(1_000_000, 3, 64, 4, 0.1, 0.025, 1e-6).unwrap();
+        assert!(layout.indeterminate_rate <= 1e-6);
+        assert_eq!(usize::from(layout.n_secondaries()), layout.sizes.len());
+        assert_eq!(layout.sizes[0], optimal_size(1_000_000, 3, 64, 4, 0.1));
+        assert_eq!(
+            layout.sizes,
+            member_sizes(layout.sizes[0], 0.1, 0.025, layout.n_secondaries())
+        );
+        // a looser target needs fewer arrays
+        let fewer = plan_layout(1_000_000, 3, 64, 4, 0.1, 0.025, 0.5).unwrap();
+        assert!(fewer.sizes.len() < layout.sizes.len());
+
+        // once less than a key is left the last array gets none
+        let tiny = plan_layout(10, 3, 64, 4, 0.5, 1., 1e-300).unwrap();
+        assert_eq!(tiny.indeterminate_rate, 0.);
+
+        // without any scaledown the primary has to meet the target by itself
+        let flat = plan_layout(1_000_000, 3, 64, 4, 1., 1., 1e-3).unwrap();
+        assert_eq!(flat.sizes, vec![optimal_size(1_000_000, 3, 64, 4, 1e-3)]);
+    }
+
+    #[test]
+    fn test_optimal_size_builds() {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let n_keys = 10_000u32;
+        let size = optimal_size(n_keys as usize, 10, 39, 4, 0.1);
+        let bfield = BField::create(
+            tmp_dir.path(),
+            "bfield",
+            size,
+            10,
+            39,
+            4,
+            0.1,
+            0.025,
+            1,
+            true,
+            String::new(),
+        )
+        .expect("to build");
+        for i in 0..n_keys {
+            bfield.insert(i.to_be_bytes(), i.into(), 0);
+        }
+        let n_indeterminate = (0..n_keys)
+            .filter(|i| bfield.get_checked(i.to_be_bytes()).is_err())
+            .count();
+        assert!(n_indeterminate > 0);
+        assert!(n_indeterminate < 1_250);
+    }
+}